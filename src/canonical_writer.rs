@@ -0,0 +1,146 @@
+use {Packed, Serialize, OutputStream, Varint};
+use output_stream::{fixed32_bytes, fixed64_bytes};
+use wire_type::{self, WireType};
+use std::io;
+
+/// Buffers each field of a message so that the message can be written with
+/// its fields in ascending field number order, regardless of the order in
+/// which `Serialize::serialize` wrote them.
+pub struct CanonicalWriter {
+    fields: Vec<(u32, Vec<u8>)>,
+    proto3: bool,
+}
+
+impl CanonicalWriter {
+    pub fn new(proto3: bool) -> CanonicalWriter {
+        CanonicalWriter {
+            fields: Vec::new(),
+            proto3: proto3,
+        }
+    }
+
+    /// Returns the encoded message. Fields sharing a field number, such as
+    /// repeated fields, keep the order in which they were written.
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        // `sort_by_key` is stable
+        self.fields.sort_by_key(|&(field, _)| field);
+
+        let len = self.fields.iter().map(|field| field.1.len()).sum();
+        let mut ret = Vec::with_capacity(len);
+
+        for (_, bytes) in self.fields {
+            ret.extend_from_slice(&bytes);
+        }
+
+        ret
+    }
+
+    /// Starts a new field and returns the buffer to write its payload to.
+    fn push(&mut self, field: u32, wire_type: WireType) -> io::Result<&mut Vec<u8>> {
+        try!(wire_type::validate_field(field));
+
+        let mut buf = Vec::new();
+        try!(((field << 3) | (wire_type as u32)).write(&mut buf));
+
+        self.fields.push((field, buf));
+        Ok(&mut self.fields.last_mut().unwrap().1)
+    }
+
+    /// Returns the buffer of the last field written, used for raw writes.
+    fn last(&mut self) -> &mut Vec<u8> {
+        if self.fields.is_empty() {
+            self.fields.push((0, Vec::new()));
+        }
+
+        &mut self.fields.last_mut().unwrap().1
+    }
+}
+
+impl OutputStream for CanonicalWriter {
+    fn write<T: ?Sized + Serialize>(&mut self, field: u32, val: &T) -> io::Result<()> {
+        try!(wire_type::validate_field(field));
+
+        if self.proto3 && val.is_default() {
+            return Ok(());
+        }
+
+        val.serialize_nested(field, self)
+    }
+
+    fn write_nested<T: ?Sized + Serialize>(&mut self, field: u32, val: &T) -> io::Result<()> {
+        let mut nested = CanonicalWriter::new(self.proto3);
+        try!(val.serialize(&mut nested));
+
+        let bytes = nested.into_bytes();
+        let buf = try!(self.push(field, WireType::LengthDelimited));
+
+        try!(bytes.len().write(buf));
+        buf.extend_from_slice(&bytes);
+
+        Ok(())
+    }
+
+    fn write_varint<T: Varint>(&mut self, field: u32, val: T) -> io::Result<()> {
+        let buf = try!(self.push(field, WireType::Varint));
+        val.write(buf)
+    }
+
+    fn write_packed<T, I>(&mut self, field: u32, vals: I) -> io::Result<()>
+            where T: Packed,
+                  I: IntoIterator<Item=T> {
+        let mut packed = Vec::new();
+
+        for val in vals {
+            try!(val.write_packed(&mut packed));
+        }
+
+        if !packed.is_empty() {
+            let buf = try!(self.push(field, WireType::LengthDelimited));
+
+            try!(packed.len().write(buf));
+            buf.extend_from_slice(&packed);
+        }
+
+        Ok(())
+    }
+
+    fn write_fixed32(&mut self, field: u32, val: u32) -> io::Result<()> {
+        let buf = try!(self.push(field, WireType::ThirtyTwoBit));
+        buf.extend_from_slice(&fixed32_bytes(val));
+        Ok(())
+    }
+
+    fn write_fixed64(&mut self, field: u32, val: u64) -> io::Result<()> {
+        let buf = try!(self.push(field, WireType::SixtyFourBit));
+        buf.extend_from_slice(&fixed64_bytes(val));
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, field: u32, val: &[u8]) -> io::Result<()> {
+        let buf = try!(self.push(field, WireType::LengthDelimited));
+
+        try!(val.len().write(buf));
+        buf.extend_from_slice(val);
+
+        Ok(())
+    }
+
+    fn write_raw_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.last().extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn write_raw_field(&mut self, field: u32, wire_type: WireType, bytes: &[u8]) -> io::Result<()> {
+        let buf = try!(self.push(field, wire_type));
+        buf.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn write_raw_varint<T: Varint>(&mut self, val: T) -> io::Result<()> {
+        val.write(self.last())
+    }
+
+    fn is_deterministic(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,319 @@
+use {deserialize, Deserialize, ErrorKind};
+use std::collections::VecDeque;
+use std::{cmp, io};
+
+/// The size of the buffer used by `Decoder::read_from`.
+const READ_BUF_LEN: usize = 8 * 1024;
+
+/// The default maximum message length, see `Decoder::set_max_message_len`.
+const DEFAULT_MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+
+/// The maximum number of bytes in a varint length prefix.
+const MAX_LENGTH_LEN: usize = 10;
+
+/// Incrementally decodes a stream of length delimited messages, as written by
+/// `serialize_delimited`, from chunks of input.
+///
+/// Unlike `InputStream`, the decoder never reads from a source itself, so it
+/// works with non-blocking I/O: input is passed to `feed` as it arrives, in
+/// chunks of any size, and the decoder keeps track of partially read length
+/// prefixes and message bodies between calls. Messages are decoded as soon as
+/// they are complete and can be taken with `next_message`.
+///
+/// ```
+/// use buffoon::Decoder;
+///
+/// let mut decoder = Decoder::<(u32, u32)>::new();
+///
+/// decoder.feed(b"\x04\x08\x01").unwrap();
+/// assert!(decoder.next_message().is_none());
+/// assert_eq!(decoder.needed(), 2);
+///
+/// decoder.feed(b"\x10\x02").unwrap();
+/// assert_eq!(decoder.next_message().unwrap().unwrap(), (1, 2));
+/// ```
+pub struct Decoder<T> {
+    state: State,
+    buf: Vec<u8>,
+    ready: VecDeque<io::Result<T>>,
+    max_len: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    // Reading the length prefix, `len` bytes of which were read so far
+    Length { val: u64, len: usize },
+    // Reading a message body of the given length into `buf`
+    Body(usize),
+}
+
+const START: State = State::Length { val: 0, len: 0 };
+
+impl<T: Deserialize> Decoder<T> {
+    pub fn new() -> Decoder<T> {
+        Decoder {
+            state: START,
+            buf: Vec::new(),
+            ready: VecDeque::new(),
+            max_len: DEFAULT_MAX_MESSAGE_LEN,
+        }
+    }
+
+    /// Sets the maximum length of a message, 64 MiB by default.
+    ///
+    /// Messages are buffered until they are complete, so the limit bounds the
+    /// memory used by the decoder. A length prefix exceeding it makes `feed`
+    /// return an `ErrorKind::MessageTooLarge` error.
+    pub fn set_max_message_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+    }
+
+    /// Returns the maximum length of a message.
+    pub fn max_message_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Consumes a chunk of input, decoding the messages it completes.
+    ///
+    /// An error is returned if a length prefix is malformed or exceeds the
+    /// maximum message length, after which the stream cannot be
+    /// resynchronized. Errors decoding a message are instead
+    /// returned by `next_message`, in place of the message.
+    pub fn feed(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        while !bytes.is_empty() {
+            match self.state {
+                State::Length { val, len } => {
+                    let byte = bytes[0];
+                    bytes = &bytes[1..];
+
+                    let bits = (byte & 0x7f) as u64;
+
+                    // The 10th byte only has room for the 64th bit and must
+                    // end the varint, as with `read_u64`
+                    if len == MAX_LENGTH_LEN - 1 && (bits > 1 || byte & 0x80 != 0) {
+                        self.state = START;
+                        return Err(ErrorKind::MalformedVarint.into());
+                    }
+
+                    let val = val | (bits << (7 * len));
+
+                    if byte & 0x80 != 0 {
+                        self.state = State::Length { val: val, len: len + 1 };
+                        continue;
+                    }
+
+                    if val > self.max_len as u64 {
+                        self.state = START;
+                        return Err(ErrorKind::MessageTooLarge.into());
+                    }
+
+                    self.state = State::Body(val as usize);
+                    self.complete();
+                }
+                State::Body(len) => {
+                    let n = cmp::min(len - self.buf.len(), bytes.len());
+
+                    self.buf.extend_from_slice(&bytes[..n]);
+                    bytes = &bytes[n..];
+
+                    self.complete();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads once from `src` and feeds the input read, returning the number
+    /// of bytes read. `Ok(0)` signals the end of the input.
+    ///
+    /// Errors from `src`, such as `WouldBlock`, are returned unchanged and
+    /// leave the decoder ready to resume once more input is available.
+    pub fn read_from<R: io::Read>(&mut self, src: &mut R) -> io::Result<usize> {
+        let mut buf = [0; READ_BUF_LEN];
+        let n = try!(src.read(&mut buf));
+
+        try!(self.feed(&buf[..n]));
+        Ok(n)
+    }
+
+    /// Returns the result of decoding the next complete message, if any.
+    pub fn next_message(&mut self) -> Option<io::Result<T>> {
+        self.ready.pop_front()
+    }
+
+    /// Returns the minimum number of bytes needed before the next message
+    /// can be complete.
+    pub fn needed(&self) -> usize {
+        match self.state {
+            State::Length { .. } => 1,
+            State::Body(len) => len - self.buf.len(),
+        }
+    }
+
+    /// Returns `true` if the input fed so far ends between two messages. At
+    /// the end of the input, any other state means it was truncated.
+    pub fn is_at_boundary(&self) -> bool {
+        matches!(self.state, State::Length { len: 0, .. })
+    }
+
+    /// Decodes the body if it was fully read.
+    fn complete(&mut self) {
+        match self.state {
+            State::Body(len) if len == self.buf.len() => {}
+            _ => return,
+        }
+
+        self.state = START;
+
+        let res = deserialize(&self.buf[..]);
+        self.buf.clear();
+
+        self.ready.push_back(res);
+    }
+}
+
+impl<T: Deserialize> Default for Decoder<T> {
+    fn default() -> Decoder<T> {
+        Decoder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {serialize_delimited, Error, ErrorKind};
+    use super::Decoder;
+    use std::io;
+
+    #[test]
+    pub fn test_decoding_byte_by_byte() {
+        let mut bytes = serialize_delimited(&(1u32, "hello".to_string())).unwrap();
+        bytes.extend(serialize_delimited(&(300u32, "".to_string())).unwrap());
+
+        let mut decoder = Decoder::<(u32, String)>::new();
+
+        for (i, byte) in bytes.iter().enumerate() {
+            assert!(decoder.needed() > 0);
+            assert_eq!(decoder.is_at_boundary(), i == 0 || i == 10);
+
+            decoder.feed(&[*byte]).unwrap();
+        }
+
+        assert!(decoder.is_at_boundary());
+        assert_eq!(decoder.next_message().unwrap().unwrap(), (1, "hello".to_string()));
+        assert_eq!(decoder.next_message().unwrap().unwrap(), (300, "".to_string()));
+        assert!(decoder.next_message().is_none());
+    }
+
+    #[test]
+    pub fn test_reporting_needed_bytes() {
+        let bytes = serialize_delimited(&(1u32, 2u32)).unwrap();
+        assert_eq!(bytes, b"\x04\x08\x01\x10\x02");
+
+        let mut decoder = Decoder::<(u32, u32)>::new();
+        assert_eq!(decoder.needed(), 1);
+
+        decoder.feed(&bytes[..2]).unwrap();
+        assert_eq!(decoder.needed(), 3);
+
+        decoder.feed(&bytes[2..]).unwrap();
+        assert_eq!(decoder.needed(), 1);
+        assert_eq!(decoder.next_message().unwrap().unwrap(), (1, 2));
+    }
+
+    #[test]
+    pub fn test_resuming_after_invalid_message() {
+        let mut decoder = Decoder::<(u32, u32)>::new();
+
+        // A truncated varint in the first message
+        decoder.feed(b"\x02\x08\x80\x04\x08\x07\x10\x00").unwrap();
+
+        let err = decoder.next_message().unwrap().unwrap_err();
+        assert_eq!(Error::from_io(&err).unwrap().kind(), ErrorKind::UnexpectedEof);
+
+        assert_eq!(decoder.next_message().unwrap().unwrap(), (7, 0));
+    }
+
+    #[test]
+    pub fn test_rejecting_malformed_length() {
+        let mut decoder = Decoder::<(u32, u32)>::new();
+
+        let err = decoder.feed(&[0xff; 10]).unwrap_err();
+        assert_eq!(Error::from_io(&err).unwrap().kind(), ErrorKind::MalformedVarint);
+
+        // A 10th byte continuing the varint
+        let mut bytes = vec![0xff; 9];
+        bytes.extend_from_slice(&[0x81, 0x00]);
+
+        let err = decoder.feed(&bytes).unwrap_err();
+        assert_eq!(Error::from_io(&err).unwrap().kind(), ErrorKind::MalformedVarint);
+    }
+
+    #[test]
+    pub fn test_rejecting_message_too_large() {
+        let mut decoder = Decoder::<(u32, u32)>::new();
+        decoder.set_max_message_len(4);
+
+        decoder.feed(b"\x04\x08\x01\x10\x02").unwrap();
+        assert_eq!(decoder.next_message().unwrap().unwrap(), (1, 2));
+
+        let err = decoder.feed(b"\x05").unwrap_err();
+        assert_eq!(Error::from_io(&err).unwrap().kind(), ErrorKind::MessageTooLarge);
+
+        // Lengths past the default limit are rejected before being buffered
+        let mut decoder = Decoder::<(u32, u32)>::new();
+
+        let err = decoder.feed(b"\xff\xff\xff\xff\x0f").unwrap_err();
+        assert_eq!(Error::from_io(&err).unwrap().kind(), ErrorKind::MessageTooLarge);
+    }
+
+    #[test]
+    pub fn test_reading_from_non_blocking_source() {
+        // Returns one byte per read, with `WouldBlock` in between
+        struct Trickle {
+            bytes: Vec<u8>,
+            pos: usize,
+            ready: bool,
+        }
+
+        impl io::Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.ready = !self.ready;
+
+                if !self.ready {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+
+                if self.pos == self.bytes.len() {
+                    return Ok(0);
+                }
+
+                buf[0] = self.bytes[self.pos];
+                self.pos += 1;
+                Ok(1)
+            }
+        }
+
+        let mut src = Trickle {
+            bytes: serialize_delimited(&(150u32, 2u32)).unwrap(),
+            pos: 0,
+            ready: false,
+        };
+
+        let mut decoder = Decoder::<(u32, u32)>::new();
+        let mut would_block = 0;
+
+        loop {
+            match decoder.read_from(&mut src) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => would_block += 1,
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+
+        assert_eq!(would_block, 6);
+        assert!(decoder.is_at_boundary());
+        assert_eq!(decoder.next_message().unwrap().unwrap(), (150, 2));
+    }
+}
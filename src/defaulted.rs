@@ -0,0 +1,235 @@
+use {Deserialize, Field, InputStream, Merge, OutputStream, Serialize};
+use std::{fmt, io};
+use std::marker::PhantomData;
+
+/// Provides the default value declared for a proto2 field with
+/// `[default = ...]`. Usually implemented with the `default_value!` macro.
+pub trait DefaultValue<T> {
+    fn default_value() -> T;
+}
+
+/// Uses `T::default()` as the default value, for fields that do not declare
+/// one.
+pub struct TypeDefault;
+
+impl<T: Default> DefaultValue<T> for TypeDefault {
+    fn default_value() -> T {
+        T::default()
+    }
+}
+
+/// A proto2 `optional` field along with its has-bit.
+///
+/// An unset field reads as its declared default, given by `D`, but is not
+/// written when serializing, while a field that was set is written even if
+/// it holds the default value. Decoding or merging the field sets it.
+///
+/// ```
+/// #[macro_use]
+/// extern crate buffoon;
+///
+/// use buffoon::Defaulted;
+///
+/// default_value!(DefaultPort: u32 = 8080);
+///
+/// # fn main() {
+/// let mut port: Defaulted<u32, DefaultPort> = Defaulted::default();
+/// assert_eq!(*port.get(), 8080);
+/// assert!(!port.is_set());
+///
+/// port.set(8080);
+/// assert!(port.is_set());
+/// # }
+/// ```
+pub struct Defaulted<T, D = TypeDefault> {
+    value: T,
+    set: bool,
+    phantom: PhantomData<fn() -> D>,
+}
+
+impl<T, D: DefaultValue<T>> Defaulted<T, D> {
+    /// Returns a field set to `value`.
+    pub fn new(value: T) -> Defaulted<T, D> {
+        Defaulted {
+            value: value,
+            set: true,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the value of the field, or its default when unset.
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    /// Returns the value of the field for modification, setting it.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.set = true;
+        &mut self.value
+    }
+
+    /// Sets the field.
+    pub fn set(&mut self, value: T) {
+        self.value = value;
+        self.set = true;
+    }
+
+    /// Returns `true` if the field was set.
+    pub fn is_set(&self) -> bool {
+        self.set
+    }
+
+    /// Unsets the field, restoring its default.
+    pub fn clear(&mut self) {
+        self.value = D::default_value();
+        self.set = false;
+    }
+
+    /// Returns the value of the field if it was set.
+    pub fn into_option(self) -> Option<T> {
+        if self.set {
+            Some(self.value)
+        } else {
+            None
+        }
+    }
+}
+
+impl<T, D: DefaultValue<T>> Default for Defaulted<T, D> {
+    fn default() -> Defaulted<T, D> {
+        Defaulted {
+            value: D::default_value(),
+            set: false,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T, D: DefaultValue<T>> From<Option<T>> for Defaulted<T, D> {
+    fn from(value: Option<T>) -> Defaulted<T, D> {
+        match value {
+            Some(value) => Defaulted::new(value),
+            None => Defaulted::default(),
+        }
+    }
+}
+
+impl<T: Clone, D> Clone for Defaulted<T, D> {
+    fn clone(&self) -> Defaulted<T, D> {
+        Defaulted {
+            value: self.value.clone(),
+            set: self.set,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: PartialEq, D> PartialEq for Defaulted<T, D> {
+    fn eq(&self, other: &Defaulted<T, D>) -> bool {
+        self.set == other.set && self.value == other.value
+    }
+}
+
+impl<T: fmt::Debug, D> fmt::Debug for Defaulted<T, D> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Defaulted")
+            .field("value", &self.value)
+            .field("set", &self.set)
+            .finish()
+    }
+}
+
+impl<T: Serialize, D> Serialize for Defaulted<T, D> {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        if self.set {
+            try!(self.value.serialize(out));
+        }
+
+        Ok(())
+    }
+
+    fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
+        if self.set {
+            try!(self.value.serialize_nested(field, out));
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Deserialize, D: DefaultValue<T>> Deserialize for Defaulted<T, D> {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+        T::deserialize(i).map(Defaulted::new)
+    }
+
+    fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Self> {
+        T::deserialize_nested(field).map(Defaulted::new)
+    }
+}
+
+impl<T: Merge, D: DefaultValue<T>> Merge for Defaulted<T, D> {
+    fn merge_from<R: io::Read>(&mut self, input: &mut InputStream<R>) -> io::Result<()> {
+        self.get_mut().merge_from(input)
+    }
+
+    fn merge_nested<R: io::Read>(&mut self, field: Field<R>) -> io::Result<()> {
+        self.get_mut().merge_nested(field)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {serialize, OutputStream, Serialize};
+    use super::{Defaulted, DefaultValue};
+    use std::io;
+
+    struct DefaultName;
+
+    impl DefaultValue<String> for DefaultName {
+        fn default_value() -> String {
+            "anonymous".to_string()
+        }
+    }
+
+    struct Msg(Defaulted<String, DefaultName>);
+
+    impl Serialize for Msg {
+        fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+            out.write(1, &self.0)
+        }
+    }
+
+    #[test]
+    pub fn test_unset_field_reads_default() {
+        let mut name: Defaulted<String, DefaultName> = Defaulted::default();
+        assert_eq!(name.get(), "anonymous");
+        assert_eq!(name.clone().into_option(), None);
+
+        name.get_mut().push('!');
+        assert!(name.is_set());
+        assert_eq!(name.get(), "anonymous!");
+
+        name.clear();
+        assert!(!name.is_set());
+        assert_eq!(name.get(), "anonymous");
+    }
+
+    #[test]
+    pub fn test_serializing_has_bit() {
+        let unset = Msg(Defaulted::default());
+        assert!(serialize(&unset).unwrap().is_empty());
+
+        let set = Msg(Defaulted::new("anonymous".to_string()));
+        assert_eq!(serialize(&set).unwrap(), b"\x0A\x09anonymous");
+    }
+
+    #[test]
+    pub fn test_reading_sets_field() {
+        let mut input = ::input_stream::from(&b"\x08\x00"[..]);
+        let f = input.read_field().unwrap().unwrap();
+
+        let val: Defaulted<u32> = f.read().unwrap();
+        assert_eq!(val, Defaulted::new(0));
+        assert!(val != Defaulted::default());
+    }
+}
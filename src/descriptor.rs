@@ -0,0 +1,70 @@
+/// Static schema information about a message type, allowing generic code such
+/// as `FieldMask` to address fields by name.
+///
+/// Descriptors are usually declared as statics:
+///
+/// ```
+/// use buffoon::{Descriptor, FieldDescriptor, MessageDescriptor};
+///
+/// struct Person;
+///
+/// static PERSON: MessageDescriptor = MessageDescriptor {
+///     name: "example.Person",
+///     fields: &[
+///         FieldDescriptor { name: "name", number: 1, repeated: false, message: None },
+///         FieldDescriptor { name: "emails", number: 2, repeated: true, message: None },
+///     ],
+/// };
+///
+/// impl Descriptor for Person {
+///     fn descriptor() -> &'static MessageDescriptor {
+///         &PERSON
+///     }
+/// }
+/// ```
+pub trait Descriptor {
+    /// Returns the descriptor of the message type.
+    fn descriptor() -> &'static MessageDescriptor;
+}
+
+/// Describes a message type.
+#[derive(Debug)]
+pub struct MessageDescriptor {
+    /// The fully qualified type name, without a leading dot.
+    pub name: &'static str,
+    /// The fields of the message, in any order.
+    pub fields: &'static [FieldDescriptor],
+}
+
+impl MessageDescriptor {
+    /// Returns the field named `name`.
+    pub fn field_by_name(&self, name: &str) -> Option<&'static FieldDescriptor> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    /// Returns the field numbered `number`.
+    pub fn field_by_number(&self, number: u32) -> Option<&'static FieldDescriptor> {
+        self.fields.iter().find(|field| field.number == number)
+    }
+}
+
+/// Describes a field of a message type.
+#[derive(Debug)]
+pub struct FieldDescriptor {
+    /// The field name, as declared in the schema.
+    pub name: &'static str,
+    /// The field number.
+    pub number: u32,
+    /// Whether the field is repeated. Map fields are repeated.
+    pub repeated: bool,
+    /// Returns the descriptor of the field type, if the field is a message.
+    /// A function is used so that recursive types can be described.
+    pub message: Option<fn() -> &'static MessageDescriptor>,
+}
+
+impl FieldDescriptor {
+    /// Returns the descriptor of the field type, if the field is a message.
+    pub fn message_descriptor(&self) -> Option<&'static MessageDescriptor> {
+        self.message.map(|descriptor| descriptor())
+    }
+}
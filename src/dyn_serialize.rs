@@ -0,0 +1,351 @@
+use {ErrorKind, InputStream, OutputStream, Packed, Serialize, Varint, WireType};
+use output_stream::{fixed32_bytes, fixed64_bytes};
+use std::io;
+
+/// An object-safe counterpart of `Serialize`, so that messages of different
+/// types can be kept together as `Box<dyn DynSerialize>` or
+/// `&dyn DynSerialize`.
+///
+/// Every `Serialize` type implements `DynSerialize`, and
+/// `dyn DynSerialize` implements `Serialize`, so trait objects are measured
+/// and written like any other message:
+///
+/// ```
+/// use buffoon::DynSerialize;
+///
+/// let queue: Vec<Box<dyn DynSerialize>> = vec![Box::new(1u32), Box::new("hi".to_string())];
+///
+/// let bytes: Vec<Vec<u8>> = queue.iter().map(|msg| buffoon::serialize(&**msg).unwrap()).collect();
+/// assert_eq!(bytes, [b"\x08\x01".to_vec(), b"\x0A\x02hi".to_vec()]);
+/// ```
+pub trait DynSerialize {
+    /// Serialize the value to the given output stream.
+    fn serialize_dyn(&self, out: &mut DynOutputStream) -> io::Result<()>;
+
+    #[doc(hidden)]
+    fn serialize_nested_dyn(&self, field: u32, out: &mut DynOutputStream) -> io::Result<()>;
+
+    #[doc(hidden)]
+    fn is_default_dyn(&self) -> bool;
+}
+
+impl<T: Serialize> DynSerialize for T {
+    fn serialize_dyn(&self, out: &mut DynOutputStream) -> io::Result<()> {
+        self.serialize(out)
+    }
+
+    fn serialize_nested_dyn(&self, field: u32, out: &mut DynOutputStream) -> io::Result<()> {
+        self.serialize_nested(field, out)
+    }
+
+    fn is_default_dyn(&self) -> bool {
+        self.is_default()
+    }
+}
+
+impl<'a> Serialize for dyn DynSerialize + 'a {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        self.serialize_dyn(&mut DynOutputStream::new(out))
+    }
+
+    fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
+        self.serialize_nested_dyn(field, &mut DynOutputStream::new(out))
+    }
+
+    fn is_default(&self) -> bool {
+        self.is_default_dyn()
+    }
+}
+
+/// The output stream given to `DynSerialize::serialize_dyn`.
+///
+/// It forwards every write to the stream serializing the trait object, such
+/// as a `Serializer` computing sizes or the writer producing bytes, so the
+/// output is the same as if the value were serialized directly. Values of
+/// packed fields are encoded one at a time as they are forwarded.
+pub struct DynOutputStream<'a> {
+    inner: &'a mut (dyn ErasedStream + 'a),
+}
+
+impl<'a> DynOutputStream<'a> {
+    fn new<O: OutputStream>(out: &'a mut O) -> DynOutputStream<'a> {
+        DynOutputStream { inner: out }
+    }
+}
+
+impl<'a> OutputStream for DynOutputStream<'a> {
+    fn write<T: ?Sized + Serialize>(&mut self, field: u32, val: &T) -> io::Result<()> {
+        self.inner.write_dyn(field, &Erased(val))
+    }
+
+    fn write_packed<T, I>(&mut self, field: u32, vals: I) -> io::Result<()>
+            where T: Packed,
+                  I: IntoIterator<Item=T> {
+        let mut vals = Encode { vals: vals.into_iter(), err: None };
+        try!(self.inner.write_packed_dyn(field, &mut vals));
+
+        match vals.err {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn write_nested<T: ?Sized + Serialize>(&mut self, field: u32, val: &T) -> io::Result<()> {
+        self.inner.write_nested_dyn(field, &Erased(val))
+    }
+
+    fn write_raw_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.inner.write_raw_bytes_dyn(bytes)
+    }
+
+    fn write_varint<T: Varint>(&mut self, field: u32, val: T) -> io::Result<()> {
+        let mut buf = [0; 10];
+        let len = try!(encode(|dst| val.write(dst), &mut buf));
+        self.inner.write_raw_field_dyn(field, WireType::Varint, &buf[..len])
+    }
+
+    fn write_raw_varint<T: Varint>(&mut self, val: T) -> io::Result<()> {
+        let mut buf = [0; 10];
+        let len = try!(encode(|dst| val.write(dst), &mut buf));
+        self.inner.write_raw_bytes_dyn(&buf[..len])
+    }
+
+    fn write_bytes(&mut self, field: u32, val: &[u8]) -> io::Result<()> {
+        self.inner.write_bytes_dyn(field, val)
+    }
+
+    fn write_fixed32(&mut self, field: u32, val: u32) -> io::Result<()> {
+        self.inner.write_raw_field_dyn(field, WireType::ThirtyTwoBit, &fixed32_bytes(val))
+    }
+
+    fn write_fixed64(&mut self, field: u32, val: u64) -> io::Result<()> {
+        self.inner.write_raw_field_dyn(field, WireType::SixtyFourBit, &fixed64_bytes(val))
+    }
+
+    fn write_raw_field(&mut self, field: u32, wire_type: WireType, bytes: &[u8]) -> io::Result<()> {
+        self.inner.write_raw_field_dyn(field, wire_type, bytes)
+    }
+
+    fn is_deterministic(&self) -> bool {
+        self.inner.is_deterministic_dyn()
+    }
+}
+
+/// The object-safe subset of `OutputStream` that `DynOutputStream` forwards
+/// to. Values are passed as `dyn DynSerialize`.
+trait ErasedStream {
+    fn write_dyn(&mut self, field: u32, val: &dyn DynSerialize) -> io::Result<()>;
+
+    fn write_nested_dyn(&mut self, field: u32, val: &dyn DynSerialize) -> io::Result<()>;
+
+    fn write_packed_dyn(&mut self, field: u32, vals: &mut dyn Iterator<Item=Encoded>) -> io::Result<()>;
+
+    fn write_bytes_dyn(&mut self, field: u32, val: &[u8]) -> io::Result<()>;
+
+    fn write_raw_field_dyn(&mut self, field: u32, wire_type: WireType, bytes: &[u8]) -> io::Result<()>;
+
+    fn write_raw_bytes_dyn(&mut self, bytes: &[u8]) -> io::Result<()>;
+
+    fn is_deterministic_dyn(&self) -> bool;
+}
+
+impl<O: OutputStream> ErasedStream for O {
+    fn write_dyn(&mut self, field: u32, val: &dyn DynSerialize) -> io::Result<()> {
+        self.write(field, val)
+    }
+
+    fn write_nested_dyn(&mut self, field: u32, val: &dyn DynSerialize) -> io::Result<()> {
+        self.write_nested(field, val)
+    }
+
+    fn write_packed_dyn(&mut self, field: u32, vals: &mut dyn Iterator<Item=Encoded>) -> io::Result<()> {
+        self.write_packed(field, vals)
+    }
+
+    fn write_bytes_dyn(&mut self, field: u32, val: &[u8]) -> io::Result<()> {
+        self.write_bytes(field, val)
+    }
+
+    fn write_raw_field_dyn(&mut self, field: u32, wire_type: WireType, bytes: &[u8]) -> io::Result<()> {
+        self.write_raw_field(field, wire_type, bytes)
+    }
+
+    fn write_raw_bytes_dyn(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write_raw_bytes(bytes)
+    }
+
+    fn is_deterministic_dyn(&self) -> bool {
+        self.is_deterministic()
+    }
+}
+
+/// Lets a possibly unsized value be passed as `dyn DynSerialize`.
+struct Erased<'a, T: ?Sized + 'a>(&'a T);
+
+impl<'a, T: ?Sized + Serialize> Serialize for Erased<'a, T> {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        self.0.serialize(out)
+    }
+
+    fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
+        self.0.serialize_nested(field, out)
+    }
+
+    fn is_default(&self) -> bool {
+        self.0.is_default()
+    }
+}
+
+/// A value of a packed field, encoded so that values of any `Packed` type
+/// are forwarded through `ErasedStream` as the same type.
+struct Encoded {
+    buf: [u8; 10],
+    len: usize,
+}
+
+impl Packed for Encoded {
+    fn packed_len(self) -> usize {
+        self.len
+    }
+
+    fn write_packed<W: io::Write>(self, dst: &mut W) -> io::Result<()> {
+        dst.write_all(&self.buf[..self.len])
+    }
+
+    fn read_packed<R: io::Read>(_: &mut InputStream<R>) -> io::Result<Option<Encoded>> {
+        Err(ErrorKind::InvalidValue("encoded packed values are write-only").into())
+    }
+
+    fn wire_type() -> WireType {
+        WireType::LengthDelimited
+    }
+}
+
+/// Encodes the values of a packed field one at a time, stopping at the first
+/// error, which is kept in `err`.
+struct Encode<I> {
+    vals: I,
+    err: Option<io::Error>,
+}
+
+impl<T: Packed, I: Iterator<Item=T>> Iterator for Encode<I> {
+    type Item = Encoded;
+
+    fn next(&mut self) -> Option<Encoded> {
+        if self.err.is_some() {
+            return None;
+        }
+
+        self.vals.next().and_then(|val| {
+            let mut buf = [0; 10];
+
+            match encode(|dst| val.write_packed(dst), &mut buf) {
+                Ok(len) => Some(Encoded { buf: buf, len: len }),
+                Err(err) => {
+                    self.err = Some(err);
+                    None
+                }
+            }
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.vals.size_hint()
+    }
+}
+
+/// Runs `write` against `buf`, returning the number of bytes written. Every
+/// varint and packed value fits in 10 bytes.
+fn encode<F>(write: F, buf: &mut [u8; 10]) -> io::Result<usize>
+        where F: FnOnce(&mut &mut [u8]) -> io::Result<()> {
+    let mut dst = &mut buf[..];
+    try!(write(&mut dst));
+
+    Ok(10 - dst.len())
+}
+
+#[cfg(test)]
+mod test {
+    use {serialize, serialize_deterministic, serialize_proto3, serializer_for, OutputStream, Serialize, SInt64};
+    use super::DynSerialize;
+    use std::collections::HashMap;
+    use std::io;
+
+    struct Inner {
+        name: String,
+    }
+
+    impl Serialize for Inner {
+        fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+            out.write(1, &self.name)
+        }
+    }
+
+    struct Outer {
+        count: u32,
+        inner: Inner,
+        samples: Vec<SInt64>,
+        weight: f64,
+        labels: HashMap<u32, u32>,
+    }
+
+    impl Serialize for Outer {
+        fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+            try!(out.write(5, &self.labels));
+            try!(out.write(1, &self.count));
+            try!(out.write(2, &self.inner));
+            try!(out.write_packed(3, self.samples.iter().cloned()));
+            try!(out.write(4, &self.weight));
+            Ok(())
+        }
+    }
+
+    fn outer() -> Outer {
+        let mut labels = HashMap::new();
+
+        for i in 0..10 {
+            labels.insert(i, i * 2);
+        }
+
+        Outer {
+            count: 0,
+            inner: Inner { name: "hello".to_string() },
+            samples: vec![SInt64(-1), SInt64(300)],
+            weight: 1.5,
+            labels: labels,
+        }
+    }
+
+    #[test]
+    pub fn test_serializing_trait_objects() {
+        let msg = outer();
+        let erased: &dyn DynSerialize = &msg;
+
+        assert_eq!(serializer_for(erased).unwrap().size(), serialize(&msg).unwrap().len());
+        assert_eq!(serialize_deterministic(erased).unwrap(), serialize_deterministic(&msg).unwrap());
+        assert_eq!(serialize_proto3(erased).unwrap(), serialize_proto3(&msg).unwrap());
+    }
+
+    #[test]
+    pub fn test_writing_trait_objects_as_fields() {
+        struct Queue(Vec<Box<dyn DynSerialize>>);
+
+        impl Serialize for Queue {
+            fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+                for msg in &self.0 {
+                    try!(out.write(1, &**msg));
+                }
+
+                Ok(())
+            }
+        }
+
+        let queue = Queue(vec![
+            Box::new(Inner { name: "a".to_string() }),
+            Box::new(7u32),
+            Box::new(Inner { name: "b".to_string() }),
+        ]);
+
+        assert_eq!(serialize(&queue).unwrap(), b"\x0A\x03\x0A\x01a\x08\x07\x0A\x03\x0A\x01b");
+    }
+}
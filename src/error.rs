@@ -0,0 +1,241 @@
+use wire_type::WireType;
+use std::{error, fmt, io};
+
+/// An error encountered while encoding or decoding Protocol Buffers data.
+///
+/// Errors are surfaced through `io::Result` and can be recovered from an
+/// `io::Error` with `Error::from_io`. When decoding, the error records the
+/// path of field numbers leading to the value that failed, outermost first,
+/// as well as the byte offset of the field being decoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    kind: ErrorKind,
+    path: Vec<u32>,
+    offset: Option<u64>,
+}
+
+/// The kind of an `Error`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The input ended in the middle of a value.
+    UnexpectedEof,
+    /// A field number was 0, too large or in the reserved range.
+    InvalidFieldNumber(u32),
+    /// A field header contained an unknown wire type.
+    InvalidWireType(u32),
+    /// A field was read using a different wire type than it was encoded with.
+    WrongWireType {
+        expected: WireType,
+        actual: WireType,
+    },
+    /// A string field was not UTF-8 encoded.
+    InvalidUtf8,
+    /// A required field was not present in the message.
+    MissingField(&'static str),
+    /// A decoded or converted value is outside of the range allowed for its
+    /// type.
+    InvalidValue(&'static str),
+    /// A `google.protobuf.Any` holds a type that is not in the registry used
+    /// to unpack it.
+    UnknownType,
+    /// A length prefix exceeded the number of bytes left in the enclosing
+    /// message.
+    LimitExceeded,
+    /// A varint was longer than 10 bytes or overflowed 64 bits.
+    MalformedVarint,
+    /// A length prefix exceeded the maximum message length accepted by a
+    /// `Decoder`.
+    MessageTooLarge,
+    /// An end group tag did not close the innermost open group.
+    UnmatchedEndGroup,
+    /// A varint did not fit in the requested type.
+    VarintOutOfRange,
+    /// The `Serializer` used to write a message was not computed for it.
+    InvalidSerializer,
+    /// The destination buffer cannot hold the serialized message.
+    BufferTooSmall,
+}
+
+impl Error {
+    /// Returns a new `Error` of the given kind with no context.
+    pub fn new(kind: ErrorKind) -> Error {
+        Error {
+            kind: kind,
+            path: Vec::new(),
+            offset: None,
+        }
+    }
+
+    /// Returns a new `ErrorKind::MissingField` error.
+    pub fn missing_field(field: &'static str) -> Error {
+        Error::new(ErrorKind::MissingField(field))
+    }
+
+    /// Returns the `Error` carried by `err`, if any.
+    pub fn from_io(err: &io::Error) -> Option<&Error> {
+        err.get_ref().and_then(|e| e.downcast_ref::<Error>())
+    }
+
+    /// Returns the kind of error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns the field numbers leading to the value that failed to decode,
+    /// outermost first.
+    pub fn path(&self) -> &[u32] {
+        &self.path
+    }
+
+    /// Returns the byte offset, from the start of the input, of the field
+    /// being decoded when the error occurred.
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    fn desc(&self) -> &'static str {
+        match self.kind {
+            ErrorKind::UnexpectedEof => "unexpected EOF",
+            ErrorKind::InvalidFieldNumber(..) => "invalid field number",
+            ErrorKind::InvalidWireType(..) => "invalid wire type",
+            ErrorKind::WrongWireType { .. } => "field has unexpected wire type",
+            ErrorKind::InvalidUtf8 => "string not UTF-8 encoded",
+            ErrorKind::MissingField(..) => "field missing but is required",
+            ErrorKind::InvalidValue(..) => "invalid value",
+            ErrorKind::UnknownType => "type not registered",
+            ErrorKind::LimitExceeded => "nested message longer than parent",
+            ErrorKind::MalformedVarint => "malformed varint",
+            ErrorKind::MessageTooLarge => "message longer than the maximum length",
+            ErrorKind::UnmatchedEndGroup => "end group tag does not match an open group",
+            ErrorKind::VarintOutOfRange => "varint out of range for target type",
+            ErrorKind::InvalidSerializer => "invalid serializer for current message",
+            ErrorKind::BufferTooSmall => "destination buffer not large enough to contain serialized message",
+        }
+    }
+
+    fn io_kind(&self) -> io::ErrorKind {
+        match self.kind {
+            ErrorKind::MalformedVarint |
+            ErrorKind::VarintOutOfRange => io::ErrorKind::InvalidData,
+            ErrorKind::InvalidSerializer => io::ErrorKind::Other,
+            _ => io::ErrorKind::InvalidInput,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::InvalidFieldNumber(val) => {
+                try!(write!(fmt, "invalid field number {}", val));
+            }
+            ErrorKind::InvalidWireType(val) => {
+                try!(write!(fmt, "invalid wire type {}", val));
+            }
+            ErrorKind::WrongWireType { expected, actual } => {
+                try!(write!(fmt, "expected {:?} field but was {:?}", expected, actual));
+            }
+            ErrorKind::MissingField(name) => {
+                try!(write!(fmt, "{} missing but is required", name));
+            }
+            ErrorKind::InvalidValue(desc) => {
+                try!(fmt.write_str(desc));
+            }
+            _ => try!(fmt.write_str(self.desc())),
+        }
+
+        if !self.path.is_empty() {
+            let path: Vec<String> = self.path.iter().map(|f| f.to_string()).collect();
+            try!(write!(fmt, "; field={}", path.join(".")));
+        }
+
+        if let Some(offset) = self.offset {
+            try!(write!(fmt, "; offset={}", offset));
+        }
+
+        Ok(())
+    }
+}
+
+impl error::Error for Error {
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error::new(kind)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        io::Error::new(err.io_kind(), err)
+    }
+}
+
+impl From<ErrorKind> for io::Error {
+    fn from(kind: ErrorKind) -> io::Error {
+        Error::new(kind).into()
+    }
+}
+
+/// Adds decoding context to `err` as it leaves the field `tag` which started
+/// at `offset`. Errors that do not carry an `Error` are returned untouched.
+pub fn annotate(err: io::Error, tag: Option<u32>, offset: u64) -> io::Error {
+    if Error::from_io(&err).is_none() {
+        return err;
+    }
+
+    let kind = err.kind();
+
+    let mut inner = match err.into_inner().map(|e| e.downcast::<Error>()) {
+        Some(Ok(inner)) => inner,
+        Some(Err(other)) => return io::Error::new(kind, other),
+        None => return kind.into(),
+    };
+
+    if let Some(tag) = tag {
+        inner.path.insert(0, tag);
+    }
+
+    if inner.offset.is_none() {
+        inner.offset = Some(offset);
+    }
+
+    (*inner).into()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use super::{Error, ErrorKind, annotate};
+
+    #[test]
+    pub fn test_converting_to_io_error() {
+        let err: io::Error = Error::new(ErrorKind::UnexpectedEof).into();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(Error::from_io(&err).unwrap().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(err.to_string(), "unexpected EOF");
+    }
+
+    #[test]
+    pub fn test_annotating_error() {
+        let err: io::Error = ErrorKind::InvalidUtf8.into();
+        let err = annotate(err, Some(5), 12);
+        let err = annotate(err, Some(1), 4);
+        let err = annotate(err, Some(2), 0);
+
+        let inner = Error::from_io(&err).unwrap();
+        assert_eq!(inner.path(), [2, 1, 5]);
+        assert_eq!(inner.offset(), Some(12));
+        assert_eq!(err.to_string(), "string not UTF-8 encoded; field=2.1.5; offset=12");
+    }
+
+    #[test]
+    pub fn test_annotating_foreign_error() {
+        let err = io::Error::new(io::ErrorKind::BrokenPipe, "boom");
+        let err = annotate(err, Some(1), 0);
+        assert!(Error::from_io(&err).is_none());
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(err.to_string(), "boom");
+    }
+}
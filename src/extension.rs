@@ -0,0 +1,221 @@
+use {merge, Deserialize, Error, ErrorKind, Merge, OutputStream, Serialize, UnknownFields};
+use serialize as encode;
+use well_known::TypeName;
+use std::any::Any as StdAny;
+use std::collections::HashMap;
+use std::io;
+use std::marker::PhantomData;
+
+/*
+ *
+ * ===== Extension =====
+ *
+ */
+
+/// A proto2 extension field of type `T`, declared with `extend` in a schema.
+///
+/// Extensions are usually declared as statics:
+///
+/// ```
+/// use buffoon::Extension;
+///
+/// static NICKNAME: Extension<String> = Extension::new("example.Person", 100, "example.nickname");
+/// ```
+pub struct Extension<T> {
+    extendee: &'static str,
+    number: u32,
+    name: &'static str,
+    phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Extension<T> {
+    /// Returns a new extension of the message type named `extendee`.
+    pub const fn new(extendee: &'static str, number: u32, name: &'static str) -> Extension<T> {
+        Extension {
+            extendee: extendee,
+            number: number,
+            name: name,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the fully qualified name of the extended message type.
+    pub fn extendee(&self) -> &'static str {
+        self.extendee
+    }
+
+    /// Returns the field number of the extension.
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// Returns the fully qualified name of the extension.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/*
+ *
+ * ===== Extendable =====
+ *
+ */
+
+/// Messages which can carry extension fields.
+///
+/// Extension values are kept encoded with the message's unknown fields and
+/// are only decoded when accessed.
+pub trait Extendable : TypeName {
+    /// Returns the fields that were not recognized when decoding the message.
+    fn unknown_fields(&self) -> &UnknownFields;
+
+    /// Returns the fields that were not recognized when decoding the message.
+    fn unknown_fields_mut(&mut self) -> &mut UnknownFields;
+
+    /// Returns `true` if the extension is set.
+    fn has_extension<T>(&self, ext: &Extension<T>) -> bool {
+        ext.extendee == Self::type_name() && self.unknown_fields().contains(ext.number)
+    }
+
+    /// Decodes a singular extension, returning `Ok(None)` if it is not set.
+    ///
+    /// When the extension occurs several times, the occurrences are merged.
+    fn get_extension<T: Merge>(&self, ext: &Extension<T>) -> io::Result<Option<T>> {
+        try!(check_extendee::<Self, T>(ext));
+
+        self.unknown_fields().read_fields(ext.number, |i| {
+            let mut ret = None;
+
+            while let Some(f) = try!(i.read_field()) {
+                try!(f.merge_into(&mut ret));
+            }
+
+            Ok(ret)
+        })
+    }
+
+    /// Decodes every occurrence of a repeated extension.
+    fn get_repeated_extension<T: Deserialize>(&self, ext: &Extension<T>) -> io::Result<Vec<T>> {
+        try!(check_extendee::<Self, T>(ext));
+
+        self.unknown_fields().read_fields(ext.number, |i| {
+            let mut ret = vec![];
+
+            while let Some(f) = try!(i.read_field()) {
+                ret.push(try!(f.read()));
+            }
+
+            Ok(ret)
+        })
+    }
+
+    /// Sets a singular extension, replacing any current value.
+    fn set_extension<T: Serialize>(&mut self, ext: &Extension<T>, val: &T) -> io::Result<()> {
+        self.set_repeated_extension(ext, Some(val))
+    }
+
+    /// Sets a repeated extension, replacing any current values.
+    fn set_repeated_extension<'a, T, I>(&mut self, ext: &Extension<T>, vals: I) -> io::Result<()>
+            where T: Serialize + 'a,
+                  I: IntoIterator<Item=&'a T> {
+        try!(check_extendee::<Self, T>(ext));
+
+        let vals: Vec<&T> = vals.into_iter().collect();
+        let bytes = try!(encode(&Repeated(ext.number, &vals)));
+
+        let unknown = self.unknown_fields_mut();
+        unknown.remove(ext.number);
+        merge(unknown, &bytes[..])
+    }
+
+    /// Removes the extension.
+    fn clear_extension<T>(&mut self, ext: &Extension<T>) {
+        if ext.extendee == Self::type_name() {
+            self.unknown_fields_mut().remove(ext.number);
+        }
+    }
+}
+
+fn check_extendee<M: ?Sized + TypeName, T>(ext: &Extension<T>) -> io::Result<()> {
+    if ext.extendee != M::type_name() {
+        let err = Error::new(ErrorKind::InvalidValue("extension does not extend message"));
+        return Err(err.into());
+    }
+
+    Ok(())
+}
+
+/// The values of a repeated extension, encoded as a message holding only the
+/// extension field.
+struct Repeated<'a, 'b: 'a, T: 'b>(u32, &'a [&'b T]);
+
+impl<'a, 'b, T: Serialize> Serialize for Repeated<'a, 'b, T> {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        out.write_repeated(self.0, self.1)
+    }
+}
+
+/*
+ *
+ * ===== ExtensionRegistry =====
+ *
+ */
+
+type Decode = fn(&UnknownFields, u32) -> io::Result<Option<Box<dyn StdAny>>>;
+
+/// Maps extended message types and field numbers to extensions, allowing the
+/// extensions set on a message to be decoded without knowing their type ahead
+/// of time.
+#[derive(Default)]
+pub struct ExtensionRegistry {
+    extensions: HashMap<(&'static str, u32), (&'static str, Decode)>,
+}
+
+impl ExtensionRegistry {
+    pub fn new() -> ExtensionRegistry {
+        ExtensionRegistry { extensions: HashMap::new() }
+    }
+
+    /// Registers a singular extension so that it can be decoded dynamically.
+    pub fn register<T: Merge + 'static>(&mut self, ext: &Extension<T>) {
+        let key = (ext.extendee, ext.number);
+        self.extensions.insert(key, (ext.name, decode::<T>));
+    }
+
+    /// Returns `true` if an extension numbered `number` was registered for the
+    /// message type named `extendee`.
+    pub fn contains(&self, extendee: &str, number: u32) -> bool {
+        self.name(extendee, number).is_some()
+    }
+
+    /// Returns the name of the extension numbered `number` registered for the
+    /// message type named `extendee`.
+    pub fn name(&self, extendee: &str, number: u32) -> Option<&'static str> {
+        self.extensions.get(&(extendee, number)).map(|&(name, _)| name)
+    }
+
+    /// Decodes the extension numbered `number` set on `msg`, returning
+    /// `Ok(None)` if it is not set. The returned value can be downcast to the
+    /// registered extension type.
+    ///
+    /// Returns an `ErrorKind::UnknownType` error if the extension was not
+    /// registered.
+    pub fn decode<M: Extendable>(&self, msg: &M, number: u32) -> io::Result<Option<Box<dyn StdAny>>> {
+        match self.extensions.get(&(M::type_name(), number)) {
+            Some(&(_, decode)) => decode(msg.unknown_fields(), number),
+            None => Err(ErrorKind::UnknownType.into()),
+        }
+    }
+}
+
+fn decode<T: Merge + 'static>(unknown: &UnknownFields, number: u32) -> io::Result<Option<Box<dyn StdAny>>> {
+    unknown.read_fields(number, |i| {
+        let mut ret: Option<T> = None;
+
+        while let Some(f) = try!(i.read_field()) {
+            try!(f.merge_into(&mut ret));
+        }
+
+        Ok(ret.map(|val| Box::new(val) as Box<dyn StdAny>))
+    })
+}
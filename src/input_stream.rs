@@ -1,5 +1,8 @@
-use {Deserialize, Varint};
+use {Deserialize, ErrorKind, Merge, Packed, RawField, Varint};
+use well_known::Wrap;
+use error::annotate;
 use take::Take;
+use types::read_u64;
 use wire_type::WireType;
 use std::{fmt, u64};
 use std::io::{self, Read};
@@ -14,6 +17,7 @@ use std::marker::PhantomData;
 /// `InputStream` allows reading Protocol Buffers encoded data off of a stream.
 pub struct InputStream<R> {
     reader: Take<R>,
+    lenient: bool,
 }
 
 pub fn from<R: Read>(read: R) -> InputStream<R> {
@@ -21,31 +25,74 @@ pub fn from<R: Read>(read: R) -> InputStream<R> {
 }
 
 impl<R: Read> InputStream<R> {
+    /// Returns a new `InputStream` reading from `reader`.
+    ///
+    /// Varints are decoded strictly: encodings longer than 10 bytes and values
+    /// that do not fit in the requested type are rejected.
+    pub fn new(reader: R) -> InputStream<R> {
+        InputStream::from(reader)
+    }
+
     fn from(reader: R) -> InputStream<R> {
-        InputStream { reader: Take::new(reader, u64::MAX) }
+        InputStream {
+            reader: Take::new(reader, u64::MAX),
+            lenient: false,
+        }
+    }
+
+    /// Sets whether varints are decoded leniently.
+    ///
+    /// In lenient mode, varints that do not fit in the requested type are
+    /// truncated, matching how protoc reads an `int32` that was written as a
+    /// 64 bit value. Varints longer than 10 bytes are rejected in either mode.
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    /// Returns `true` if varints are decoded leniently.
+    pub fn is_lenient(&self) -> bool {
+        self.lenient
+    }
+
+    /// Returns the number of bytes read from the underlying reader so far.
+    pub fn position(&self) -> u64 {
+        self.reader.position()
     }
 
     /// Reads the a field header and returns a `Field` which allows reading the
     /// field data.
     pub fn read_field(&mut self) -> io::Result<Option<Field<R>>> {
+        let offset = self.position();
+
         // Read the header byte. In this case, EOF errors are OK as they signify
         // that there is no field to read
         let head = match self.read_varint::<u32>() {
             Ok(Some(h)) => h,
             Ok(None) => return Ok(None),
-            Err(e) => return Err(e),
+            Err(e) => return Err(annotate(e, None, offset)),
         };
 
         // Extract the type of the field
         let wire_type = match WireType::from_u32(head & 0x7) {
             Some(res) => res,
-            None => return Err(unexpected_output("invalid wire type"))
+            None => {
+                let err = ErrorKind::InvalidWireType(head & 0x7).into();
+                return Err(annotate(err, None, offset));
+            }
         };
 
+        // Field 0 is never valid. Reserved field numbers are accepted, as they
+        // are by protoc, since only schemas are forbidden from using them.
+        if head >> 3 == 0 {
+            let err = ErrorKind::InvalidFieldNumber(0).into();
+            return Err(annotate(err, None, offset));
+        }
+
         Ok(Some(Field {
             input: self,
             tag: head >> 3,
-            wire_type: wire_type
+            wire_type: wire_type,
+            offset: offset,
         }))
     }
 
@@ -54,7 +101,59 @@ impl<R: Read> InputStream<R> {
     /// If at EOF before reading the first byte, returns Ok(None).
     #[doc(hidden)]
     pub fn read_varint<T: Varint>(&mut self) -> io::Result<Option<T>> {
-        T::read(&mut self.reader)
+        match try!(read_u64(&mut self.reader, self.lenient)) {
+            Some(val) => T::from_u64(val, self.lenient).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read a little-endian fixed 32 bit value.
+    ///
+    /// If at EOF before reading the first byte, returns Ok(None).
+    #[doc(hidden)]
+    pub fn read_fixed32(&mut self) -> io::Result<Option<u32>> {
+        let mut buf = [0; 4];
+
+        if !try!(self.read_fixed(&mut buf)) {
+            return Ok(None);
+        }
+
+        Ok(Some(buf.iter().rev().fold(0, |ret, &byte| (ret << 8) | byte as u32)))
+    }
+
+    /// Read a little-endian fixed 64 bit value.
+    ///
+    /// If at EOF before reading the first byte, returns Ok(None).
+    #[doc(hidden)]
+    pub fn read_fixed64(&mut self) -> io::Result<Option<u64>> {
+        let mut buf = [0; 8];
+
+        if !try!(self.read_fixed(&mut buf)) {
+            return Ok(None);
+        }
+
+        Ok(Some(buf.iter().rev().fold(0, |ret, &byte| (ret << 8) | byte as u64)))
+    }
+
+    /// Fills `buf`, returning `false` if at EOF before reading the first byte.
+    fn read_fixed(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        let mut off = 0;
+
+        while off < buf.len() {
+            let cnt = try!(self.reader.read(&mut buf[off..]));
+
+            if cnt == 0 {
+                if off == 0 {
+                    return Ok(false);
+                }
+
+                return Err(eof());
+            }
+
+            off += cnt;
+        }
+
+        Ok(true)
     }
 
     /// Reads a length delimited field and returns the data as `Vec<u8>`
@@ -67,6 +166,14 @@ impl<R: Read> InputStream<R> {
         Ok(None)
     }
 
+    /// Reads the remaining bytes of the current message
+    #[doc(hidden)]
+    pub fn read_to_end(&mut self) -> io::Result<Vec<u8>> {
+        let mut ret = Vec::new();
+        try!(self.reader.read_to_end(&mut ret));
+        Ok(ret)
+    }
+
     /// Skips the current field
     fn skip(&mut self, n: usize) -> io::Result<usize> {
         let mut i = 0;
@@ -111,18 +218,56 @@ impl<R: Read> InputStream<R> {
         Ok(ret)
     }
 
+    /// Skips the fields of a group up to and including the end group tag
+    /// closing it, returning the position of that tag.
+    fn skip_group(&mut self, tag: u32) -> io::Result<u64> {
+        // Nested groups are tracked here rather than by recursing, so that
+        // deeply nested input cannot overflow the stack
+        let mut open = vec![tag];
+
+        loop {
+            let pos = self.position();
+
+            let f = match try!(self.read_field()) {
+                Some(f) => f,
+                None => return Err(eof()),
+            };
+
+            match f.wire_type {
+                WireType::StartGroup => open.push(f.tag),
+                WireType::EndGroup => {
+                    if open.pop() != Some(f.tag) {
+                        return Err(ErrorKind::UnmatchedEndGroup.into());
+                    }
+
+                    if open.is_empty() {
+                        return Ok(pos);
+                    }
+                }
+                _ => try!(f.skip_inner()),
+            }
+        }
+    }
+
     /// Reads and deserializes a nested message.
     fn read_message<T: Deserialize>(&mut self) -> io::Result<Option<T>> {
+        self.read_delimited(T::deserialize)
+    }
+
+    /// Reads a length prefix and calls `action` with the stream limited to
+    /// that many bytes.
+    fn read_delimited<F, T>(&mut self, action: F) -> io::Result<Option<T>>
+            where F: FnOnce(&mut InputStream<R>) -> io::Result<T> {
         if let Some(len) = try!(self.read_varint::<u64>()) {
             let lim = self.reader.limit();
 
             if len > lim {
-                return Err(unexpected_output("nested message longer than parent"));
+                return Err(ErrorKind::LimitExceeded.into());
             }
 
             self.reader.set_limit(len);
 
-            let ret = T::deserialize(self).map(Some);
+            let ret = action(self).map(Some);
 
             let consumed = len - self.reader.limit();
             self.reader.set_limit(lim - consumed);
@@ -155,7 +300,8 @@ impl<R: Read> InputStream<R> {
 pub struct Field<'a, R: 'a> {
     input: &'a mut InputStream<R>,
     tag: u32,
-    wire_type: WireType
+    wire_type: WireType,
+    offset: u64,
 }
 
 impl<'a, R: Read> Field<'a, R> {
@@ -164,8 +310,24 @@ impl<'a, R: Read> Field<'a, R> {
         self.tag
     }
 
+    /// Get the field wire type
+    pub fn wire_type(&self) -> WireType {
+        self.wire_type
+    }
+
+    /// Get the position of the field header in the input
+    #[doc(hidden)]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
     /// Skip the current field
     pub fn skip(self) -> io::Result<()> {
+        let (tag, offset) = (self.tag, self.offset);
+        self.skip_inner().map_err(|e| annotate(e, Some(tag), offset))
+    }
+
+    fn skip_inner(self) -> io::Result<()> {
         match self.wire_type {
             WireType::Varint => {
                 if let Some(_) = try!(self.input.read_varint::<u64>()) {
@@ -174,7 +336,13 @@ impl<'a, R: Read> Field<'a, R> {
 
                 Err(eof())
             }
-            WireType::SixtyFourBit => unimplemented!(),
+            WireType::SixtyFourBit => {
+                if 8 == try!(self.input.skip(8)) {
+                    return Ok(());
+                }
+
+                Err(eof())
+            }
             WireType::LengthDelimited => {
                 if let Some(len) = try!(self.input.read_varint::<usize>()) {
                     if len == try!(self.input.skip(len)) {
@@ -184,27 +352,149 @@ impl<'a, R: Read> Field<'a, R> {
 
                 Err(eof())
             }
-            WireType::StartGroup => unimplemented!(),
-            WireType::EndGroup => unimplemented!(),
-            WireType::ThirtyTwoBit => unimplemented!()
+            WireType::StartGroup => {
+                let tag = self.tag;
+                self.input.skip_group(tag).map(|_| ())
+            }
+            // Only valid when closing a group, which `skip_group` consumes
+            WireType::EndGroup => Err(ErrorKind::UnmatchedEndGroup.into()),
+            WireType::ThirtyTwoBit => {
+                if 4 == try!(self.input.skip(4)) {
+                    return Ok(());
+                }
+
+                Err(eof())
+            }
+        }
+    }
+
+    /// Read the field's payload without decoding it, so that the field can
+    /// be written back out unchanged.
+    ///
+    /// The payload holds the bytes following the field header as they were
+    /// encoded, except for the length prefix of length delimited fields and
+    /// the end group tag of groups.
+    pub fn read_raw(self) -> io::Result<RawField> {
+        let (tag, wire_type, offset) = (self.tag, self.wire_type, self.offset);
+        let input = self.input;
+
+        let start = input.position();
+        input.reader.start_recording();
+
+        let res = match wire_type {
+            WireType::StartGroup => input.skip_group(tag).map(|end| (end - start) as usize),
+            _ => {
+                let field = Field { input: &mut *input, tag: tag, wire_type: wire_type, offset: offset };
+                field.skip_inner().map(|_| (input.position() - start) as usize)
+            }
+        };
+
+        let mut payload = input.reader.stop_recording();
+
+        let end = match res {
+            Ok(end) => end,
+            Err(e) => return Err(annotate(e, Some(tag), offset)),
+        };
+
+        payload.truncate(end);
+
+        if wire_type == WireType::LengthDelimited {
+            // Strip the length prefix
+            let prefix = {
+                let mut rest = &payload[..];
+                try!(read_u64(&mut rest, false));
+                payload.len() - rest.len()
+            };
+
+            payload.drain(..prefix);
         }
+
+        Ok(RawField {
+            tag: tag,
+            wire_type: wire_type,
+            payload: payload,
+        })
     }
 
     pub fn read<T: Deserialize>(self) -> io::Result<T> {
-        T::deserialize_nested(self)
+        let (tag, offset) = (self.tag, self.offset);
+        T::deserialize_nested(self).map_err(|e| annotate(e, Some(tag), offset))
+    }
+
+    /// Read a scalar encoded as its `google.protobuf.*Value` wrapper message
+    pub fn read_wrapped<T: Wrap>(self) -> io::Result<T> {
+        self.read::<T::Wrapper>().map(Into::into)
+    }
+
+    /// Merge the field into an existing value. Scalars are replaced while
+    /// nested messages are merged recursively.
+    pub fn merge_into<T: Merge>(self, dst: &mut T) -> io::Result<()> {
+        let (tag, offset) = (self.tag, self.offset);
+        dst.merge_nested(self).map_err(|e| annotate(e, Some(tag), offset))
     }
 
-    pub fn read_packed<T: Varint>(self) -> io::Result<Varints<'a, T, R>> {
+    /// Read a packed repeated field, returning an iterator over its values
+    pub fn read_packed<T: Packed>(self) -> io::Result<PackedValues<'a, T, R>> {
+        let (tag, offset) = (self.tag, self.offset);
+
         match self.wire_type {
             WireType::LengthDelimited => {
-                let len = try!(self.input.read_varint::<u64>()).unwrap_or(0);
-                let input = InputStream::from((&mut self.input.reader).take(len));
-                Ok(Varints {
+                let len = match self.input.read_varint::<u64>() {
+                    Ok(len) => len.unwrap_or(0),
+                    Err(e) => return Err(annotate(e, Some(tag), offset)),
+                };
+
+                let mut input = InputStream::from((&mut self.input.reader).take(len));
+                input.set_lenient(self.input.lenient);
+
+                Ok(PackedValues {
                     input: input,
+                    tag: tag,
+                    offset: offset,
                     phantom: PhantomData,
                 })
             }
-            _ => Err(unexpected_output("field type was not length delimited")),
+            actual => {
+                let err = wrong_wire_type(WireType::LengthDelimited, actual);
+                Err(annotate(err, Some(tag), offset))
+            }
+        }
+    }
+
+    /// Read one occurrence of a repeated scalar field, appending its values
+    /// to `dst`.
+    ///
+    /// Both the packed and unpacked encodings are accepted, as protobuf
+    /// parsers must, so a field may be written either way, or both ways
+    /// within one message. If an error is returned, values read before it
+    /// may have been appended.
+    pub fn read_repeated<T: Packed>(self, dst: &mut Vec<T>) -> io::Result<()> {
+        if self.wire_type == WireType::LengthDelimited {
+            for val in try!(self.read_packed()) {
+                dst.push(try!(val));
+            }
+
+            return Ok(());
+        }
+
+        let (tag, offset) = (self.tag, self.offset);
+
+        let res = if self.wire_type != T::wire_type() {
+            Err(wrong_wire_type(T::wire_type(), self.wire_type))
+        } else {
+            match T::read_packed(self.input) {
+                Ok(Some(val)) => Ok(val),
+                Ok(None) => Err(eof()),
+                Err(e) => Err(e),
+            }
+        };
+
+        match res {
+            Ok(val) => {
+                dst.push(val);
+                Ok(())
+            }
+            Err(e) => Err(annotate(e, Some(tag), offset)),
         }
     }
 
@@ -218,7 +508,21 @@ impl<'a, R: Read> Field<'a, R> {
 
                 Err(eof())
             }
-            _ => Err(unexpected_output("field type was not length delimited")),
+            actual => Err(wrong_wire_type(WireType::LengthDelimited, actual)),
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn merge_nested<T: Merge>(self, dst: &mut T) -> io::Result<()> {
+        match self.wire_type {
+            WireType::LengthDelimited => {
+                if let Some(()) = try!(self.input.read_delimited(|i| dst.merge_from(i))) {
+                    return Ok(());
+                }
+
+                Err(eof())
+            }
+            actual => Err(wrong_wire_type(WireType::LengthDelimited, actual)),
         }
     }
 
@@ -226,13 +530,41 @@ impl<'a, R: Read> Field<'a, R> {
     pub fn read_varint<T: Varint>(self) -> io::Result<T> {
         match self.wire_type {
             WireType::Varint => {
-                if let Some(val) = try!(T::read(&mut self.input.reader)) {
+                if let Some(val) = try!(self.input.read_varint()) {
                     return Ok(val);
                 }
 
                 Err(eof())
             }
-            _ => Err(unexpected_output("field type was not varint"))
+            actual => Err(wrong_wire_type(WireType::Varint, actual)),
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn read_fixed32(self) -> io::Result<u32> {
+        match self.wire_type {
+            WireType::ThirtyTwoBit => {
+                if let Some(val) = try!(self.input.read_fixed32()) {
+                    return Ok(val);
+                }
+
+                Err(eof())
+            }
+            actual => Err(wrong_wire_type(WireType::ThirtyTwoBit, actual)),
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn read_fixed64(self) -> io::Result<u64> {
+        match self.wire_type {
+            WireType::SixtyFourBit => {
+                if let Some(val) = try!(self.input.read_fixed64()) {
+                    return Ok(val);
+                }
+
+                Err(eof())
+            }
+            actual => Err(wrong_wire_type(WireType::SixtyFourBit, actual)),
         }
     }
 
@@ -246,7 +578,7 @@ impl<'a, R: Read> Field<'a, R> {
 
                 Err(eof())
             }
-            _ => Err(unexpected_output("field type was not length delimited"))
+            actual => Err(wrong_wire_type(WireType::LengthDelimited, actual)),
         }
     }
 }
@@ -259,23 +591,25 @@ impl<'a, R> fmt::Debug for Field<'a, R> {
 
 /*
  *
- * ===== Varints =====
+ * ===== PackedValues =====
  *
  */
 
-pub struct Varints<'a, T: Varint, R: 'a> {
+pub struct PackedValues<'a, T: Packed, R: 'a> {
     input: InputStream<io::Take<&'a mut Take<R>>>,
+    tag: u32,
+    offset: u64,
     phantom: PhantomData<T>,
 }
 
-impl<'a, T: Varint, R: 'a + io::Read> Iterator for Varints<'a, T, R> {
+impl<'a, T: Packed, R: 'a + io::Read> Iterator for PackedValues<'a, T, R> {
     type Item = io::Result<T>;
 
     fn next(&mut self) -> Option<io::Result<T>> {
-        match T::read(&mut self.input.reader) {
+        match T::read_packed(&mut self.input) {
             Ok(Some(v)) => Some(Ok(v)),
             Ok(None) => None,
-            Err(e) => Some(Err(e)),
+            Err(e) => Some(Err(annotate(e, Some(self.tag), self.offset))),
         }
     }
 }
@@ -286,12 +620,12 @@ impl<'a, T: Varint, R: 'a + io::Read> Iterator for Varints<'a, T, R> {
  *
  */
 
-fn unexpected_output(desc: &'static str) -> io::Error {
-    io::Error::new(io::ErrorKind::InvalidInput, desc)
+fn wrong_wire_type(expected: WireType, actual: WireType) -> io::Error {
+    ErrorKind::WrongWireType { expected: expected, actual: actual }.into()
 }
 
 fn eof() -> io::Error {
-    return unexpected_output("unexpected EOF");
+    ErrorKind::UnexpectedEof.into()
 }
 
 #[cfg(test)]
@@ -320,10 +654,10 @@ mod test {
 
     #[test]
     pub fn test_reading_single_byte_usize() {
-        with_input_stream(b"\x00\x08", |i| {
+        with_input_stream(b"\x08\x08", |i| {
             {
                 let f = i.read_field().unwrap().unwrap();
-                assert_eq!(f.tag(), 0);
+                assert_eq!(f.tag(), 1);
                 assert_eq!(f.read::<u64>().unwrap(), 8);
             }
 
@@ -333,10 +667,10 @@ mod test {
 
     #[test]
     pub fn test_reading_multi_byte_usize() {
-        with_input_stream(b"\x00\x92\x0C", |i| {
+        with_input_stream(b"\x08\x92\x0C", |i| {
             {
                 let f = i.read_field().unwrap().unwrap();
-                assert_eq!(f.tag(), 0);
+                assert_eq!(f.tag(), 1);
                 assert_eq!(f.read::<u64>().unwrap(), 1554);
             }
 
@@ -346,10 +680,10 @@ mod test {
 
     #[test]
     pub fn test_reading_sequential_fields() {
-        with_input_stream(b"\x00\x08\x0A\x04zomg\x12\x03lol", |i| {
+        with_input_stream(b"\x18\x08\x0A\x04zomg\x12\x03lol", |i| {
             {
                 let f = i.read_field().unwrap().unwrap();
-                assert_eq!(f.tag(), 0);
+                assert_eq!(f.tag(), 3);
                 assert_eq!(f.read::<u64>().unwrap(), 8);
             }
 
@@ -371,7 +705,7 @@ mod test {
 
     #[test]
     pub fn test_skipping_string_field() {
-        with_input_stream(b"\x00\x08\x0A\x04zomg\x12\x03lol", |i| {
+        with_input_stream(b"\x18\x08\x0A\x04zomg\x12\x03lol", |i| {
             i.read_field().unwrap().unwrap().skip().unwrap();
 
             {
@@ -386,6 +720,22 @@ mod test {
         });
     }
 
+    #[test]
+    pub fn test_skipping_fixed_width_fields() {
+        with_input_stream(b"\x0D\x01\x02\x03\x04\x11\x01\x02\x03\x04\x05\x06\x07\x08\x18\x01", |i| {
+            i.read_field().unwrap().unwrap().skip().unwrap();
+            i.read_field().unwrap().unwrap().skip().unwrap();
+
+            let f = i.read_field().unwrap().unwrap();
+            assert_eq!(f.tag(), 3);
+            assert_eq!(f.read::<u32>().unwrap(), 1);
+        });
+
+        with_input_stream(b"\x0D\x01\x02", |i| {
+            assert!(i.read_field().unwrap().unwrap().skip().is_err());
+        });
+    }
+
     #[test]
     pub fn test_reading_multi_byte_tag_field() {
         with_input_stream(b"\x92\x01\x04zomg", |i| {
@@ -420,6 +770,55 @@ mod test {
         })
     }
 
+    #[test]
+    pub fn test_skipping_every_wire_type() {
+        let bytes = b"\x08\x96\x01\x11\x01\x00\x00\x00\x00\x00\x00\x00\x1A\x01a\
+                      \x23\x2B\x2C\x08\x01\x24\x2D\x01\x00\x00\x00\x30\x05";
+
+        with_input_stream(bytes, |i| {
+            for _ in 0..5 {
+                i.read_field().unwrap().unwrap().skip().unwrap();
+            }
+
+            let f = i.read_field().unwrap().unwrap();
+            assert_eq!(f.tag(), 6);
+            assert_eq!(f.read::<u32>().unwrap(), 5);
+        });
+    }
+
+    #[test]
+    pub fn test_reading_field_zero() {
+        with_input_stream(b"\x00\x08", |i| {
+            assert!(i.read_field().is_err());
+        });
+    }
+
+    #[test]
+    pub fn test_reading_out_of_range_varint_field() {
+        with_input_stream(b"\x08\xac\x02", |i| {
+            let f = i.read_field().unwrap().unwrap();
+            assert!(f.read::<u8>().is_err());
+        });
+
+        with_input_stream(b"\x08\xac\x02", |i| {
+            i.set_lenient(true);
+
+            let f = i.read_field().unwrap().unwrap();
+            assert_eq!(f.read::<u8>().unwrap(), 44);
+        });
+    }
+
+    #[test]
+    pub fn test_reading_packed_varints_leniently() {
+        with_input_stream(b"\x22\x03\x01\xac\x02", |i| {
+            i.set_lenient(true);
+
+            let f = i.read_field().unwrap().unwrap();
+            let nums: Vec<u8> = f.read_packed().unwrap().map(Result::unwrap).collect();
+            assert_eq!(nums, [1, 44]);
+        })
+    }
+
     fn with_input_stream<F: FnOnce(&mut InputStream<Cursor<&[u8]>>)>(bytes: &[u8], action: F) {
         let mut input = InputStream::from(Cursor::new(bytes));
         action(&mut input)
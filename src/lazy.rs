@@ -0,0 +1,192 @@
+use {deserialize, Deserialize, Field, InputStream, Merge, OutputStream, Serialize};
+use std::{fmt, io, mem};
+
+/// A nested message that is decoded on first access.
+///
+/// When read, only the encoded bytes of the message are kept. They are
+/// decoded the first time the value is accessed and, as long as the value is
+/// not modified, written back unchanged when serializing. Merging further
+/// occurrences of the field into an unmodified value appends their bytes, as
+/// concatenated encodings merge when decoded.
+///
+/// Accessors take `&mut self` so that the decoded value can be kept. Comparing
+/// or serializing a value that was not accessed decodes it without keeping
+/// it.
+///
+/// In deterministic mode, the value is decoded and re-encoded so that the
+/// output is canonical.
+#[derive(Clone)]
+pub struct Lazy<T> {
+    state: State<T>,
+}
+
+#[derive(Clone)]
+enum State<T> {
+    // The encoded value, not decoded yet
+    Raw(Vec<u8>),
+    // The decoded value, along with its encoding until it is modified
+    Decoded(T, Option<Vec<u8>>),
+}
+
+impl<T> Lazy<T> {
+    /// Returns a decoded value.
+    pub fn new(value: T) -> Lazy<T> {
+        Lazy { state: State::Decoded(value, None) }
+    }
+
+    /// Returns a value that will be decoded from `bytes` on first access.
+    pub fn from_bytes(bytes: Vec<u8>) -> Lazy<T> {
+        Lazy { state: State::Raw(bytes) }
+    }
+
+    /// Returns the encoded value, unless it was modified since being read.
+    pub fn raw_bytes(&self) -> Option<&[u8]> {
+        match self.state {
+            State::Raw(ref raw) |
+            State::Decoded(_, Some(ref raw)) => Some(raw),
+            State::Decoded(_, None) => None,
+        }
+    }
+
+    /// Returns `true` if the value was decoded.
+    pub fn is_decoded(&self) -> bool {
+        match self.state {
+            State::Raw(..) => false,
+            State::Decoded(..) => true,
+        }
+    }
+}
+
+impl<T: Deserialize> Lazy<T> {
+    /// Returns the value, decoding it if needed.
+    pub fn get(&mut self) -> io::Result<&T> {
+        self.decoded().map(|(val, _)| &*val)
+    }
+
+    /// Returns the value for modification, decoding it if needed. The value
+    /// is re-encoded when serializing.
+    pub fn get_mut(&mut self) -> io::Result<&mut T> {
+        let (val, raw) = try!(self.decoded());
+        *raw = None;
+
+        Ok(val)
+    }
+
+    /// Returns the value, decoding it if needed.
+    pub fn into_inner(self) -> io::Result<T> {
+        match self.state {
+            State::Raw(raw) => deserialize(&raw[..]),
+            State::Decoded(val, _) => Ok(val),
+        }
+    }
+
+    /// Decodes the value if needed, returning it along with its encoding.
+    fn decoded(&mut self) -> io::Result<(&mut T, &mut Option<Vec<u8>>)> {
+        match self.state {
+            State::Decoded(ref mut val, ref mut raw) => Ok((val, raw)),
+            State::Raw(ref mut raw) => {
+                let val = try!(deserialize(&raw[..]));
+                let raw = mem::take(raw);
+
+                self.state = State::Decoded(val, Some(raw));
+                self.decoded()
+            }
+        }
+    }
+
+    /// Calls `f` with the value, decoding it without keeping it if needed.
+    fn with<F, U>(&self, f: F) -> io::Result<U>
+            where F: FnOnce(&T) -> U {
+        match self.state {
+            State::Decoded(ref val, _) => Ok(f(val)),
+            State::Raw(ref raw) => {
+                let val = try!(deserialize(&raw[..]));
+                Ok(f(&val))
+            }
+        }
+    }
+}
+
+/// The default value is the empty encoding, which decodes as the default
+/// message and keeps fields merged into it undecoded.
+impl<T> Default for Lazy<T> {
+    fn default() -> Lazy<T> {
+        Lazy::from_bytes(Vec::new())
+    }
+}
+
+impl<T: Deserialize + PartialEq> PartialEq for Lazy<T> {
+    fn eq(&self, other: &Lazy<T>) -> bool {
+        match self.with(|a| other.with(|b| a == b)) {
+            Ok(Ok(eq)) => eq,
+            // Values that fail to decode are only equal to the same bytes
+            _ => self.raw_bytes() == other.raw_bytes(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Lazy<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.state {
+            State::Decoded(ref val, _) => fmt.debug_tuple("Lazy").field(val).finish(),
+            State::Raw(ref raw) => fmt.debug_struct("Lazy").field("raw", raw).finish(),
+        }
+    }
+}
+
+impl<T: Serialize + Deserialize> Serialize for Lazy<T> {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        match self.raw_bytes() {
+            Some(raw) if !out.is_deterministic() => out.write_raw_bytes(raw),
+            _ => try!(self.with(|val| val.serialize(out))),
+        }
+    }
+
+    fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
+        match self.raw_bytes() {
+            Some(raw) if !out.is_deterministic() => out.write_bytes(field, raw),
+            _ => try!(self.with(|val| val.serialize_nested(field, out))),
+        }
+    }
+}
+
+impl<T: Deserialize> Deserialize for Lazy<T> {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Lazy<T>> {
+        i.read_to_end().map(Lazy::from_bytes)
+    }
+
+    fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Lazy<T>> {
+        field.read_bytes().map(Lazy::from_bytes)
+    }
+}
+
+impl<T: Merge> Merge for Lazy<T> {
+    fn merge_from<R: io::Read>(&mut self, input: &mut InputStream<R>) -> io::Result<()> {
+        let raw = match self.state {
+            State::Raw(ref mut raw) |
+            State::Decoded(_, Some(ref mut raw)) => {
+                raw.extend_from_slice(&try!(input.read_to_end()));
+                mem::take(raw)
+            }
+            State::Decoded(ref mut val, None) => return val.merge_from(input),
+        };
+
+        // A decoded value no longer reflects the bytes
+        self.state = State::Raw(raw);
+        Ok(())
+    }
+
+    fn merge_nested<R: io::Read>(&mut self, field: Field<R>) -> io::Result<()> {
+        let raw = match self.state {
+            State::Raw(ref mut raw) |
+            State::Decoded(_, Some(ref mut raw)) => {
+                raw.extend_from_slice(&try!(field.read_bytes()));
+                mem::take(raw)
+            }
+            State::Decoded(ref mut val, None) => return val.merge_nested(field),
+        };
+
+        self.state = State::Raw(raw);
+        Ok(())
+    }
+}
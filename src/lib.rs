@@ -1,10 +1,26 @@
 #[macro_use]
 extern crate log;
 
+#[cfg(feature = "serde_json")]
+extern crate serde_json;
+
+pub use defaulted::{Defaulted, DefaultValue, TypeDefault};
+pub use decoder::Decoder;
+pub use dyn_serialize::{DynOutputStream, DynSerialize};
+pub use descriptor::{Descriptor, FieldDescriptor, MessageDescriptor};
+pub use error::{Error, ErrorKind};
+pub use extension::{Extendable, Extension, ExtensionRegistry};
 pub use input_stream::{InputStream, Field};
+pub use lazy::Lazy;
 pub use output_stream::OutputStream;
+pub use projection::{Projected, Projection};
+pub use raw_field::RawField;
 pub use serializer::Serializer;
-pub use types::Varint;
+pub use stream_writer::StreamWriter;
+pub use types::{Fixed32, Fixed64, Packed, SFixed32, SFixed64, SInt32, SInt64, Varint};
+pub use unknown_fields::{UnknownFields, UnknownValue};
+pub use visitor::{visit, Visitor};
+pub use wire_type::WireType;
 
 use std::io::{self, Read};
 
@@ -16,22 +32,51 @@ macro_rules! required {
         match $name {
             Some(val) => val,
             None => {
-                return Err(::std::io::Error::new(
-                            ::std::io::ErrorKind::InvalidInput,
-                            concat!($field, " missing but is required")));
+                return Err($crate::Error::missing_field($field).into());
             }
         }
     })
 }
 
+// Declare the default value of a proto2 field, for use with `Defaulted`
+#[macro_export]
+macro_rules! default_value {
+    ($(#[$attr:meta])* $vis:vis $name:ident : $ty:ty = $val:expr) => {
+        $(#[$attr])*
+        $vis struct $name;
+
+        impl $crate::DefaultValue<$ty> for $name {
+            fn default_value() -> $ty {
+                $val
+            }
+        }
+    }
+}
+
+mod error;
+mod extension;
+mod canonical_writer;
+mod decoder;
+mod defaulted;
+mod descriptor;
+mod dyn_serialize;
 mod input_stream;
+mod lazy;
 mod output_stream;
 mod output_writer;
+mod projection;
+mod raw_field;
 mod serializer;
+mod stream_writer;
 mod take;
 mod types;
+mod vectored_writer;
+mod unknown_fields;
+mod visitor;
 mod wire_type;
 
+pub mod well_known;
+
 /// Deserialize an encoded Protocol Buffers message.
 pub fn deserialize<T, R>(input: R) -> io::Result<T>
         where T: Deserialize,
@@ -39,19 +84,81 @@ pub fn deserialize<T, R>(input: R) -> io::Result<T>
     T::deserialize(&mut input_stream::from(input))
 }
 
-pub fn serializer_for<T: Serialize>(msg: &T) -> io::Result<Serializer> {
+/// Merge an encoded Protocol Buffers message into an existing one.
+pub fn merge<T, R>(msg: &mut T, input: R) -> io::Result<()>
+        where T: Merge,
+              R: io::Read {
+    msg.merge_from(&mut input_stream::from(input))
+}
+
+pub fn serializer_for<T: ?Sized + Serialize>(msg: &T) -> io::Result<Serializer> {
+    serializer_with(msg, Serializer::new())
+}
+
+pub fn serialize<T: ?Sized + Serialize>(msg: &T) -> io::Result<Vec<u8>> {
+    serialize_with(msg, Serializer::new())
+}
+
+/// Serialize a message prefixed with its length as a varint, so that several
+/// messages can be written to the same stream and read back with a `Decoder`.
+pub fn serialize_delimited<T: ?Sized + Serialize>(msg: &T) -> io::Result<Vec<u8>> {
+    let serializer = try!(serializer_for(msg));
+    let mut bytes = Vec::with_capacity(serializer.size() + 10);
+
+    try!(serializer.size().write(&mut bytes));
+    try!(serializer.serialize(msg, &mut bytes));
+
+    Ok(bytes)
+}
+
+/// Serialize a message deterministically, see `Serializer::set_deterministic`.
+pub fn serialize_deterministic<T: ?Sized + Serialize>(msg: &T) -> io::Result<Vec<u8>> {
     let mut serializer = Serializer::new();
+    serializer.set_deterministic(true);
+
+    serialize_with(msg, serializer)
+}
+
+/// Serialize a message omitting default scalars, see `Serializer::set_proto3`.
+pub fn serialize_proto3<T: ?Sized + Serialize>(msg: &T) -> io::Result<Vec<u8>> {
+    let mut serializer = Serializer::new();
+    serializer.set_proto3(true);
+
+    serialize_with(msg, serializer)
+}
+
+/// Returns `true` if the fields of the encoded message `bytes` appear in
+/// ascending field number order, as written by `serialize_deterministic`.
+///
+/// Only the top-level fields are checked: the wire format does not tell
+/// nested messages apart from strings and bytes.
+pub fn is_canonical(bytes: &[u8]) -> io::Result<bool> {
+    let mut input = input_stream::from(bytes);
+    let mut last = 0;
 
+    while let Some(f) = try!(input.read_field()) {
+        if f.tag() < last {
+            return Ok(false);
+        }
+
+        last = f.tag();
+        try!(f.skip());
+    }
+
+    Ok(true)
+}
+
+fn serializer_with<T: ?Sized + Serialize>(msg: &T, mut serializer: Serializer) -> io::Result<Serializer> {
     // populate the message size info
     try!(msg.serialize(&mut serializer));
 
     Ok(serializer)
 }
 
-pub fn serialize<T: Serialize>(msg: &T) -> io::Result<Vec<u8>> {
+fn serialize_with<T: ?Sized + Serialize>(msg: &T, serializer: Serializer) -> io::Result<Vec<u8>> {
     trace!("serializing message");
     trace!("  - reading nested message lengths");
-    let serializer = try!(serializer_for(msg));
+    let serializer = try!(serializer_with(msg, serializer));
     let mut bytes = vec![0u8; serializer.size()];
 
     trace!("  - writing serialized message");
@@ -60,6 +167,9 @@ pub fn serialize<T: Serialize>(msg: &T) -> io::Result<Vec<u8>> {
 }
 
 /// A trait for values which can be serialized
+///
+/// Scalars, such as strings and integers, serialized on their own rather than
+/// as a field are encoded as their `google.protobuf.*Value` wrapper message.
 pub trait Serialize {
     /// Serialize the value to the given output stream.
     fn serialize<O>(&self, out: &mut O) -> io::Result<()> where O: OutputStream;
@@ -77,6 +187,15 @@ pub trait Serialize {
     fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
         out.write_nested(field, self)
     }
+
+    /// Returns `true` if the value is the proto3 default of a scalar field,
+    /// in which case it is omitted when writing proto3 messages. Messages and
+    /// `Option` values are never considered defaults, preserving their
+    /// presence.
+    #[doc(hidden)]
+    fn is_default(&self) -> bool {
+        false
+    }
 }
 
 /// A trait for values which can be deserialized
@@ -87,12 +206,59 @@ pub trait Deserialize : Sized {
     fn deserialize_nested<R: Read>(field: Field<R>) -> io::Result<Self> {
         field.read_nested()
     }
+
+    /// Reads an occurrence of a repeated field, appending the values it
+    /// holds to `dst`. Scalars that can be packed accept both packed and
+    /// unpacked occurrences.
+    #[doc(hidden)]
+    fn deserialize_repeated<R: Read>(field: Field<R>, dst: &mut Vec<Self>) -> io::Result<()> {
+        dst.push(try!(field.read()));
+        Ok(())
+    }
+
+    /// Returns a collector for the occurrences of the field holding the
+    /// value if the value is written as a repeated field, such as an array,
+    /// in which case every occurrence is read as part of the value.
+    #[doc(hidden)]
+    fn occurrences<'a, R: Read + 'a>() -> Option<Box<dyn Occurrences<Self, R> + 'a>> where Self: 'a {
+        None
+    }
+}
+
+/// Collects the occurrences of a repeated field into a single value, see
+/// `Deserialize::occurrences`.
+#[doc(hidden)]
+pub trait Occurrences<T, R: Read> {
+    /// Read an occurrence of the field
+    fn read(&mut self, field: Field<R>) -> io::Result<()>;
+
+    /// Returns the value once every occurrence has been read
+    fn finish(self: Box<Self>) -> io::Result<T>;
+}
+
+/// A trait for values which can be updated from an encoded message.
+///
+/// Merging follows Protocol Buffers semantics for concatenated encodings:
+/// scalar fields are replaced by the last value read, repeated fields are
+/// appended to and nested messages are merged recursively. Implementations
+/// usually handle scalar and repeated fields by assigning or pushing the
+/// result of `Field::read`, and nested messages with `Field::merge_into`.
+pub trait Merge : Deserialize {
+    /// Merge the encoded message into the value
+    fn merge_from<R: Read>(&mut self, input: &mut InputStream<R>) -> io::Result<()>;
+
+    /// Merge the value nested in a protobuf message using the given field.
+    /// Usually, this function doesn't need to be implemented. Scalars
+    /// implement it in order to replace the current value.
+    fn merge_nested<R: Read>(&mut self, field: Field<R>) -> io::Result<()> {
+        field.merge_nested(self)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use std::io;
-    use super::{Serialize, OutputStream, serialize};
+    use super::{Error, ErrorKind, Serialize, OutputStream, serialize};
 
     #[test]
     pub fn test_writing_unit_struct() {
@@ -140,4 +306,41 @@ mod test {
 
         assert_eq!(bytes, expect);
     }
+
+    #[test]
+    pub fn test_writing_invalid_field_numbers() {
+        struct Field(u32);
+
+        impl Serialize for Field {
+            fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+                out.write(self.0, &1u32)
+            }
+        }
+
+        for &field in [0, 19_000, 19_999, 1 << 29, !0].iter() {
+            let err = serialize(&Field(field)).unwrap_err();
+            let err = Error::from_io(&err).unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidFieldNumber(field));
+        }
+
+        for &field in [1, 18_999, 20_000, (1 << 29) - 1].iter() {
+            assert!(serialize(&Field(field)).is_ok());
+        }
+    }
+
+    #[test]
+    pub fn test_serializing_empty_packed_varints() {
+        struct Simple;
+
+        impl Serialize for Simple {
+            fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+                try!(out.write_packed(4, Vec::<u32>::new()));
+                try!(out.write(5, &1u32));
+                Ok(())
+            }
+        }
+
+        let bytes = serialize(&Simple).unwrap();
+        assert_eq!(bytes, b"\x28\x01");
+    }
 }
@@ -1,5 +1,6 @@
-use {Serialize, Varint};
-use wire_type::WireType;
+use {Packed, Serialize, Varint};
+use well_known::{Wrap, Wrapped};
+use wire_type::{self, WireType};
 use std::io;
 
 pub trait OutputStream {
@@ -11,22 +12,40 @@ pub trait OutputStream {
      */
 
     /// Writes a nested message with the specified field number
+    ///
+    /// Returns an error if the field number is 0, larger than 2^29 - 1 or in
+    /// the reserved 19000-19999 range.
     fn write<T: ?Sized + Serialize>(&mut self, field: u32, val: &T) -> io::Result<()>;
 
     /// Write a repeated message field
+    ///
+    /// Every value is written, including scalars at their default value.
     fn write_repeated<T, I>(&mut self, field: u32, msgs: I) -> io::Result<()>
             where T: Serialize,
                   I: IntoIterator<Item=T> {
         for msg in msgs {
-            try!(self.write(field, &msg));
+            // Written as present, so that defaults are kept in proto3
+            try!(self.write(field, &Some(msg)));
         }
 
         Ok(())
     }
 
-    /// Write a list of repeated varints in packed format
+    /// Write an optional scalar using the encoding of its
+    /// `google.protobuf.*Value` wrapper message, preserving its presence.
+    fn write_wrapped<T: Wrap>(&mut self, field: u32, val: &Option<T>) -> io::Result<()> {
+        match *val {
+            Some(ref val) => self.write(field, &Wrapped(val)),
+            None => Ok(()),
+        }
+    }
+
+    /// Write a list of repeated scalars in packed format
+    ///
+    /// Varints, `bool`, floats and the `Fixed32`, `Fixed64`, `SFixed32`,
+    /// `SFixed64`, `SInt32` and `SInt64` encodings can be packed.
     fn write_packed<T, I>(&mut self, field: u32, vals: I) -> io::Result<()>
-            where T: Varint,
+            where T: Packed,
                   I: IntoIterator<Item=T>;
 
     /*
@@ -49,12 +68,51 @@ pub trait OutputStream {
 
     #[doc(hidden)]
     fn write_bytes(&mut self, field: u32, val: &[u8]) -> io::Result<()>;
+
+    /// Writes a field header followed by `bytes` as they are.
+    #[doc(hidden)]
+    fn write_raw_field(&mut self, field: u32, wire_type: WireType, bytes: &[u8]) -> io::Result<()> {
+        try!(write_head(self, field, wire_type));
+        self.write_raw_bytes(bytes)
+    }
+
+    #[doc(hidden)]
+    fn write_fixed32(&mut self, field: u32, val: u32) -> io::Result<()> {
+        try!(write_head(self, field, WireType::ThirtyTwoBit));
+        self.write_raw_bytes(&fixed32_bytes(val))
+    }
+
+    #[doc(hidden)]
+    fn write_fixed64(&mut self, field: u32, val: u64) -> io::Result<()> {
+        try!(write_head(self, field, WireType::SixtyFourBit));
+        self.write_raw_bytes(&fixed64_bytes(val))
+    }
+
+    /// Returns `true` if the output must be byte-stable, in which case values
+    /// with no inherent order, such as map entries, are written sorted.
+    #[doc(hidden)]
+    fn is_deterministic(&self) -> bool {
+        false
+    }
 }
 
 // Interal helper
-pub fn write_head<O: OutputStream>(out: &mut O, field: u32, wire_type: WireType) -> io::Result<()> {
-    // TODO: Handle overflow
+pub fn write_head<O: ?Sized + OutputStream>(out: &mut O, field: u32, wire_type: WireType) -> io::Result<()> {
+    try!(wire_type::validate_field(field));
+
     let bits = (field << 3) | (wire_type as u32);
     try!(out.write_raw_varint(bits));
     Ok(())
 }
+
+/// Returns the little-endian encoding of a fixed 32 bit value
+pub fn fixed32_bytes(val: u32) -> [u8; 4] {
+    [val as u8, (val >> 8) as u8, (val >> 16) as u8, (val >> 24) as u8]
+}
+
+/// Returns the little-endian encoding of a fixed 64 bit value
+pub fn fixed64_bytes(val: u64) -> [u8; 8] {
+    let lo = fixed32_bytes(val as u32);
+    let hi = fixed32_bytes((val >> 32) as u32);
+    [lo[0], lo[1], lo[2], lo[3], hi[0], hi[1], hi[2], hi[3]]
+}
@@ -1,19 +1,21 @@
-use {Serialize, OutputStream, Varint};
+use {ErrorKind, Packed, Serialize, OutputStream, Varint};
 use output_stream::write_head;
-use wire_type::WireType;
+use wire_type::{self, WireType};
 use std::io::{self, Write};
 
 pub struct OutputWriter<'a, W:'a> {
     curr: usize,
     nested: &'a [usize],
+    proto3: bool,
     writer: &'a mut W
 }
 
 impl<'a, W: Write> OutputWriter<'a, W> {
-    pub fn new(nested: &'a [usize], writer: &'a mut W) -> OutputWriter<'a, W> {
+    pub fn new(nested: &'a [usize], proto3: bool, writer: &'a mut W) -> OutputWriter<'a, W> {
         OutputWriter {
             curr: 0,
             nested: nested,
+            proto3: proto3,
             writer: writer
         }
     }
@@ -21,6 +23,12 @@ impl<'a, W: Write> OutputWriter<'a, W> {
 
 impl<'a, W: Write> OutputStream for OutputWriter<'a, W> {
     fn write<T: ?Sized + Serialize>(&mut self, field: u32, val: &T) -> io::Result<()> {
+        try!(wire_type::validate_field(field));
+
+        if self.proto3 && val.is_default() {
+            return Ok(());
+        }
+
         val.serialize_nested(field, self)
     }
 
@@ -39,11 +47,9 @@ impl<'a, W: Write> OutputStream for OutputWriter<'a, W> {
         try!(write_head(self, field, WireType::LengthDelimited));
         try!(self.write_raw_varint(size));
 
-        if size > 0 {
-            try!(val.serialize(self));
-        }
-
-        Ok(())
+        // Serialized even when empty, as empty packed fields in the message
+        // still hold a slot in the nested sizes
+        val.serialize(self)
     }
 
     fn write_varint<T: Varint>(&mut self, field: u32, val: T) -> io::Result<()> {
@@ -53,7 +59,7 @@ impl<'a, W: Write> OutputStream for OutputWriter<'a, W> {
     }
 
     fn write_packed<T, I>(&mut self, field: u32, vals: I) -> io::Result<()>
-            where T: Varint,
+            where T: Packed,
                   I: IntoIterator<Item=T> {
         if self.curr >= self.nested.len() {
             return invalid_serializer();
@@ -67,7 +73,7 @@ impl<'a, W: Write> OutputStream for OutputWriter<'a, W> {
             try!(self.write_raw_varint(size));
 
             for val in vals {
-                try!(self.write_raw_varint(val));
+                try!(val.write_packed(&mut self.writer));
             }
         };
 
@@ -92,5 +98,5 @@ impl<'a, W: Write> OutputStream for OutputWriter<'a, W> {
 }
 
 fn invalid_serializer<T>() -> io::Result<T> {
-    Err(io::Error::new(io::ErrorKind::Other, "invalid serializer for current message"))
+    Err(ErrorKind::InvalidSerializer.into())
 }
@@ -0,0 +1,369 @@
+use {Deserialize, Error, ErrorKind, InputStream, UnknownValue, WireType};
+use std::io::{self, Read};
+
+/// Extracts the values at a set of field paths from an encoded message,
+/// skipping every other field, without a type describing the message.
+///
+/// A path is a sequence of field numbers leading from the outer message to a
+/// value, such as `2.1` for field 1 of the message in field 2. A `*` segment
+/// matches any field number, so `4.*.3` selects field 3 of any message nested
+/// in field 4. Each occurrence of a repeated field yields a value.
+///
+/// ```
+/// use buffoon::{InputStream, Projection};
+///
+/// // 2: { 1: 150 }, 3: "skipped"
+/// let bytes = b"\x12\x03\x08\x96\x01\x1A\x07skipped";
+///
+/// let projection = Projection::parse(&["2.1"]).unwrap();
+/// let values = projection.read(&mut InputStream::new(&bytes[..])).unwrap();
+///
+/// assert_eq!(values[0].path, [2, 1]);
+/// assert_eq!(values[0].decode::<u32>().unwrap(), 150);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Projection {
+    paths: Vec<Vec<Segment>>,
+}
+
+/// A value extracted by a `Projection`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Projected {
+    /// The index of the projection path that selected the value, in the order
+    /// paths were added.
+    pub index: usize,
+    /// The field numbers leading to the value, with wildcards resolved.
+    pub path: Vec<u32>,
+    /// The encoded value.
+    pub value: UnknownValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Segment {
+    Field(u32),
+    Any,
+}
+
+impl Projection {
+    pub fn new() -> Projection {
+        Projection { paths: Vec::new() }
+    }
+
+    /// Returns a projection of the given paths, such as `"2.1"` or
+    /// `"4.*.3"`.
+    pub fn parse(paths: &[&str]) -> Result<Projection, Error> {
+        let mut ret = Projection::new();
+
+        for path in paths {
+            try!(ret.add_str(path));
+        }
+
+        Ok(ret)
+    }
+
+    /// Adds a path of field numbers, returning its index.
+    pub fn add(&mut self, path: &[u32]) -> Result<usize, Error> {
+        if path.is_empty() || path.contains(&0) {
+            return Err(invalid_path());
+        }
+
+        self.paths.push(path.iter().map(|&n| Segment::Field(n)).collect());
+        Ok(self.paths.len() - 1)
+    }
+
+    /// Adds a path written as field numbers or `*` separated by `.`,
+    /// returning its index.
+    pub fn add_str(&mut self, path: &str) -> Result<usize, Error> {
+        let mut segments = vec![];
+
+        for segment in path.split('.') {
+            segments.push(match segment {
+                "*" => Segment::Any,
+                _ => match segment.parse() {
+                    Ok(0) | Err(_) => return Err(invalid_path()),
+                    Ok(n) => Segment::Field(n),
+                },
+            });
+        }
+
+        self.paths.push(segments);
+        Ok(self.paths.len() - 1)
+    }
+
+    /// Reads the message, returning the selected values in the order they
+    /// were encoded, values nested in a message coming before the message.
+    ///
+    /// Fields are only decoded as messages when a path descends into them.
+    /// Length delimited fields matched by a `*` segment that do not hold a
+    /// message, such as strings, are ignored. Groups are skipped: paths do
+    /// not descend into them and they yield no value.
+    pub fn read<R: Read>(&self, input: &mut InputStream<R>) -> io::Result<Vec<Projected>> {
+        let all: Vec<usize> = (0..self.paths.len()).collect();
+        let mut ret = vec![];
+
+        while let Some(f) = try!(input.read_field()) {
+            let tag = f.tag();
+            let matched = self.matching(&all, 0, tag);
+
+            match f.wire_type() {
+                WireType::LengthDelimited if !matched.nested.is_empty() => {
+                    let val = try!(f.read_bytes());
+                    try!(self.descend(&val, tag, matched, &mut ret));
+                }
+                _ if !matched.leaf.is_empty() => {
+                    if let Some(value) = try!(UnknownValue::read(f)) {
+                        select(&matched.leaf, &[tag], value, &mut ret);
+                    }
+                }
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Visits the message `val` held by the top-level field `tag`.
+    ///
+    /// Nested messages are borrowed from `val` and tracked on a stack rather
+    /// than by recursing, and fields that no path selects are stepped over.
+    fn descend(&self, val: &[u8], tag: u32, matched: Match, out: &mut Vec<Projected>) -> io::Result<()> {
+        let mut stack = vec![Frame { matched: matched, val: val, next: &[], len: out.len() }];
+        // The tags of the messages on the stack
+        let mut path = vec![tag];
+        let mut rest = val;
+
+        loop {
+            let step = match stack.last() {
+                Some(frame) => self.step(rest, &frame.matched.nested, &mut path, out),
+                None => return Ok(()),
+            };
+
+            match step {
+                Ok(Step::Next(next)) => rest = next,
+                Ok(Step::Enter(tag, matched, val, next)) => {
+                    stack.push(Frame { matched: matched, val: val, next: next, len: out.len() });
+                    path.push(tag);
+                    rest = val;
+                }
+                Ok(Step::Exit) => {
+                    if let Some(frame) = stack.pop() {
+                        let value = UnknownValue::LengthDelimited(frame.val.to_vec());
+                        select(&frame.matched.leaf, &path, value, out);
+
+                        path.pop();
+                        rest = frame.next;
+                    }
+                }
+                Err(e) => {
+                    // A field matched by `*` may not hold a message: discard
+                    // anything it yielded and carry on after it
+                    let i = match stack.iter().rposition(|frame| frame.matched.wildcard) {
+                        Some(i) => i,
+                        None => return Err(e),
+                    };
+
+                    stack.truncate(i + 1);
+                    path.truncate(i + 1);
+                    out.truncate(stack[i].len);
+                    rest = &[];
+                }
+            }
+        }
+    }
+
+    /// Reads a field from `rest`, the remaining fields of the message at
+    /// `path`. `active` holds the paths matching the message.
+    fn step<'b>(&self,
+                rest: &'b [u8],
+                active: &[usize],
+                path: &mut Vec<u32>,
+                out: &mut Vec<Projected>) -> io::Result<Step<'b>> {
+        let mut input = InputStream::new(rest);
+
+        let f = match try!(input.read_field()) {
+            Some(f) => f,
+            None => return Ok(Step::Exit),
+        };
+
+        let tag = f.tag();
+        let matched = self.matching(active, path.len(), tag);
+
+        match f.wire_type() {
+            WireType::LengthDelimited => {
+                // Only the length prefix is read, the value is borrowed
+                let len = match try!(input.read_varint::<usize>()) {
+                    Some(len) => len,
+                    None => return Err(ErrorKind::UnexpectedEof.into()),
+                };
+
+                let pos = input.position() as usize;
+
+                if len > rest.len() - pos {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+
+                let (val, next) = rest[pos..].split_at(len);
+
+                if !matched.nested.is_empty() {
+                    return Ok(Step::Enter(tag, matched, val, next));
+                }
+
+                path.push(tag);
+                select(&matched.leaf, path, UnknownValue::LengthDelimited(val.to_vec()), out);
+                path.pop();
+
+                return Ok(Step::Next(next));
+            }
+            _ if !matched.leaf.is_empty() => {
+                if let Some(value) = try!(UnknownValue::read(f)) {
+                    path.push(tag);
+                    select(&matched.leaf, path, value, out);
+                    path.pop();
+                }
+            }
+            _ => try!(f.skip()),
+        }
+
+        Ok(Step::Next(&rest[input.position() as usize..]))
+    }
+
+    /// Returns the paths, out of `active`, matching the field `tag` of a
+    /// message found `depth` fields deep.
+    fn matching(&self, active: &[usize], depth: usize, tag: u32) -> Match {
+        let mut ret = Match { leaf: vec![], nested: vec![], wildcard: true };
+
+        for &index in active {
+            let segments = &self.paths[index];
+
+            match segments[depth] {
+                Segment::Field(n) if n == tag => ret.wildcard = false,
+                Segment::Field(_) => continue,
+                Segment::Any => {}
+            }
+
+            if segments.len() == depth + 1 {
+                ret.leaf.push(index);
+            } else {
+                ret.nested.push(index);
+            }
+        }
+
+        ret
+    }
+}
+
+/// The projection paths matching a field.
+struct Match {
+    // The paths ending at the field
+    leaf: Vec<usize>,
+    // The paths descending into the field
+    nested: Vec<usize>,
+    // Whether the field is only matched by `*` segments
+    wildcard: bool,
+}
+
+/// A message being visited by `Projection::descend`.
+struct Frame<'b> {
+    matched: Match,
+    // The encoded message
+    val: &'b [u8],
+    // The fields of the enclosing message following the message
+    next: &'b [u8],
+    // The number of values yielded before entering the message
+    len: usize,
+}
+
+enum Step<'b> {
+    // Carry on with the remaining fields
+    Next(&'b [u8]),
+    // Visit a nested message, followed by the remaining fields
+    Enter(u32, Match, &'b [u8], &'b [u8]),
+    // The end of the message was reached
+    Exit,
+}
+
+impl Projected {
+    /// Decodes the value as a `T`, as if it were read from a field declared
+    /// with type `T`.
+    pub fn decode<T: Deserialize>(&self) -> io::Result<T> {
+        self.value.decode()
+    }
+}
+
+/// Yields `value`, found at `path`, for each of the `leaf` paths.
+fn select(leaf: &[usize], path: &[u32], value: UnknownValue, out: &mut Vec<Projected>) {
+    for &index in leaf {
+        out.push(Projected {
+            index: index,
+            path: path.to_vec(),
+            value: value.clone(),
+        });
+    }
+}
+
+fn invalid_path() -> Error {
+    Error::new(ErrorKind::InvalidValue("invalid field path"))
+}
+
+#[cfg(test)]
+mod test {
+    use {InputStream, UnknownValue};
+    use super::Projection;
+
+    #[test]
+    pub fn test_parsing_paths() {
+        assert!(Projection::parse(&["1", "2.1", "4.*.3", "*"]).is_ok());
+
+        for path in ["", "0", "1..2", "a", "1.-2", "1.**"].iter() {
+            assert!(Projection::parse(&[path]).is_err(), "{:?}", path);
+        }
+
+        assert!(Projection::new().add(&[]).is_err());
+        assert!(Projection::new().add(&[1, 0]).is_err());
+    }
+
+    #[test]
+    pub fn test_projecting_top_level_fields() {
+        let bytes = b"\x08\x01\x12\x02hi\x08\x02\x1D\x01\x00\x00\x00";
+
+        let mut projection = Projection::new();
+        assert_eq!(projection.add(&[1]).unwrap(), 0);
+        assert_eq!(projection.add(&[3]).unwrap(), 1);
+
+        let values = projection.read(&mut InputStream::new(&bytes[..])).unwrap();
+        let values: Vec<_> = values.into_iter().map(|v| (v.index, v.value)).collect();
+
+        assert_eq!(values, vec![
+            (0, UnknownValue::Varint(1)),
+            (0, UnknownValue::Varint(2)),
+            (1, UnknownValue::Fixed32(1)),
+        ]);
+    }
+
+    #[test]
+    pub fn test_projecting_message_and_its_fields() {
+        // 1: { 2: { 1: 5 }, 3: "\xff" }
+        let bytes = b"\x0A\x07\x12\x02\x08\x05\x1A\x01\xff";
+
+        let projection = Projection::parse(&["1", "1.*.1"]).unwrap();
+        let values = projection.read(&mut InputStream::new(&bytes[..])).unwrap();
+        let values: Vec<_> = values.into_iter().map(|v| (v.index, v.path, v.value)).collect();
+
+        assert_eq!(values, vec![
+            (1, vec![1, 2, 1], UnknownValue::Varint(5)),
+            (0, vec![1], UnknownValue::LengthDelimited(bytes[2..].to_vec())),
+        ]);
+    }
+
+    #[test]
+    pub fn test_skipping_groups() {
+        // 1: group { 1: 5 }, 1: 7
+        let bytes = b"\x0B\x08\x05\x0C\x08\x07";
+
+        let projection = Projection::parse(&["1", "1.1"]).unwrap();
+        let values = projection.read(&mut InputStream::new(&bytes[..])).unwrap();
+
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0].value, UnknownValue::Varint(7));
+    }
+}
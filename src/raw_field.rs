@@ -0,0 +1,122 @@
+use {OutputStream, Serialize, WireType};
+use std::io;
+
+/// A field read with `Field::read_raw`, holding its payload as it was
+/// encoded.
+///
+/// Writing a `RawField` reproduces the field byte for byte, which lets
+/// generic proxies copy fields they do not understand through verbatim:
+///
+/// ```
+/// use buffoon::{InputStream, RawField};
+///
+/// // A fixed32 field followed by a group holding a varint
+/// let bytes = b"\x0D\x01\x00\x00\x00\x13\x08\x96\x01\x14";
+///
+/// let mut input = InputStream::new(&bytes[..]);
+/// let mut fields = vec![];
+///
+/// while let Some(f) = input.read_field().unwrap() {
+///     fields.push(f.read_raw().unwrap());
+/// }
+///
+/// assert_eq!(fields[1].payload, b"\x08\x96\x01");
+///
+/// let mut out = vec![];
+///
+/// for field in &fields {
+///     out.extend(buffoon::serialize(field).unwrap());
+/// }
+///
+/// assert_eq!(out, &bytes[..]);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawField {
+    /// The field number.
+    pub tag: u32,
+    /// The wire type the field was encoded with.
+    pub wire_type: WireType,
+    /// The bytes following the field header, without the length prefix of
+    /// length delimited fields or the end group tag of groups.
+    pub payload: Vec<u8>,
+}
+
+impl Serialize for RawField {
+    /// Writes the field with its own field number.
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        self.serialize_nested(self.tag, out)
+    }
+
+    /// Writes the payload, with the given field number.
+    fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
+        match self.wire_type {
+            WireType::LengthDelimited => out.write_bytes(field, &self.payload),
+            WireType::StartGroup => {
+                try!(out.write_raw_field(field, WireType::StartGroup, &self.payload));
+                out.write_raw_field(field, WireType::EndGroup, &[])
+            }
+            wire_type => out.write_raw_field(field, wire_type, &self.payload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {serialize, serialize_deterministic, Error, ErrorKind, InputStream, WireType};
+    use super::RawField;
+    use std::io;
+
+    fn read_raw(bytes: &[u8]) -> io::Result<Vec<RawField>> {
+        let mut input = InputStream::new(bytes);
+        let mut ret = vec![];
+
+        while let Some(f) = try!(input.read_field()) {
+            ret.push(try!(f.read_raw()));
+        }
+
+        Ok(ret)
+    }
+
+    #[test]
+    pub fn test_reading_raw_fields() {
+        // An over-long varint, fixed64, bytes, nested groups and fixed32
+        let bytes = b"\x08\x81\x00\x11\x01\x02\x03\x04\x05\x06\x07\x08\x1A\x02hi\
+                      \x23\x2B\x08\x01\x2C\x30\x02\x24\x3D\x01\x00\x00\x00";
+
+        let fields = read_raw(&bytes[..]).unwrap();
+
+        assert_eq!(fields, vec![
+            RawField { tag: 1, wire_type: WireType::Varint, payload: b"\x81\x00".to_vec() },
+            RawField { tag: 2, wire_type: WireType::SixtyFourBit, payload: b"\x01\x02\x03\x04\x05\x06\x07\x08".to_vec() },
+            RawField { tag: 3, wire_type: WireType::LengthDelimited, payload: b"hi".to_vec() },
+            RawField { tag: 4, wire_type: WireType::StartGroup, payload: b"\x2B\x08\x01\x2C\x30\x02".to_vec() },
+            RawField { tag: 7, wire_type: WireType::ThirtyTwoBit, payload: b"\x01\x00\x00\x00".to_vec() },
+        ]);
+
+        let mut out = vec![];
+
+        for field in &fields {
+            out.extend(serialize(field).unwrap());
+            assert_eq!(serialize_deterministic(field).unwrap(), serialize(field).unwrap());
+        }
+
+        assert_eq!(out, &bytes[..]);
+    }
+
+    #[test]
+    pub fn test_reading_invalid_groups() {
+        let cases: [(&[u8], ErrorKind); 3] = [
+            // End group closing another group
+            (b"\x0B\x14", ErrorKind::UnmatchedEndGroup),
+            // Stray end group
+            (b"\x0C", ErrorKind::UnmatchedEndGroup),
+            // Unterminated group
+            (b"\x0B\x08\x01", ErrorKind::UnexpectedEof),
+        ];
+
+        for &(bytes, kind) in cases.iter() {
+            let err = read_raw(bytes).unwrap_err();
+            assert_eq!(Error::from_io(&err).unwrap().kind(), kind, "{:?}", bytes);
+        }
+    }
+}
@@ -1,19 +1,25 @@
-use {Serialize, OutputStream, Varint};
+use {ErrorKind, Packed, Serialize, OutputStream, Varint};
+use canonical_writer::CanonicalWriter;
 use output_stream::write_head;
 use output_writer::OutputWriter;
-use wire_type::WireType;
-use std::io;
+use vectored_writer::{write_all_vectored, VectoredWriter};
+use wire_type::{self, WireType};
+use std::io::{self, IoSlice, Write};
 
 pub struct Serializer {
     size: usize,
-    nested: Vec<usize>
+    nested: Vec<usize>,
+    deterministic: bool,
+    proto3: bool,
 }
 
 impl Serializer {
     pub fn new() -> Serializer {
         Serializer {
             size: 0,
-            nested: Vec::new()
+            nested: Vec::new(),
+            deterministic: false,
+            proto3: false,
         }
     }
 
@@ -21,19 +27,88 @@ impl Serializer {
         self.size
     }
 
-    pub fn serialize<T: Serialize, W: io::Write>(&self, msg: &T, writer: &mut W) -> io::Result<()> {
-        let mut out = OutputWriter::new(&self.nested, writer);
+    /// Sets whether messages are serialized deterministically.
+    ///
+    /// In deterministic mode, the fields of every message are written in
+    /// ascending field number order, regardless of the order in which
+    /// `Serialize::serialize` writes them, and map entries are sorted by key.
+    /// Fields sharing a field number keep the order they were written in. The
+    /// output is byte-stable, at the cost of buffering each message in memory
+    /// while writing it.
+    ///
+    /// The mode must be set before the message sizes are computed.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Returns `true` if messages are serialized deterministically.
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Sets whether scalar fields at their default value are omitted.
+    ///
+    /// In proto3 mode, values written with `OutputStream::write` are skipped
+    /// when they are `0`, `false`, an empty string or empty bytes, matching
+    /// the proto3 implicit presence rules that protoc follows. Optional
+    /// fields keep their presence by being written as an `Option`, so that
+    /// `Some(0)` is written. Repeated values and map entries are always
+    /// written.
+    ///
+    /// The mode must be set before the message sizes are computed.
+    pub fn set_proto3(&mut self, proto3: bool) {
+        self.proto3 = proto3;
+    }
+
+    /// Returns `true` if scalar fields at their default value are omitted.
+    pub fn is_proto3(&self) -> bool {
+        self.proto3
+    }
+
+    pub fn serialize<T: ?Sized + Serialize, W: io::Write>(&self, msg: &T, writer: &mut W) -> io::Result<()> {
+        if self.deterministic {
+            let mut out = CanonicalWriter::new(self.proto3);
+            try!(msg.serialize(&mut out));
+            return writer.write_all(&out.into_bytes());
+        }
+
+        let mut out = OutputWriter::new(&self.nested, self.proto3, writer);
 
         try!(msg.serialize(&mut out));
 
         Ok(())
     }
 
-    pub fn serialize_into<T: Serialize>(&self, msg: &T, dst: &mut [u8]) -> io::Result<()> {
+    /// Serializes the message as a sequence of `IoSlice`s passed to `f` in
+    /// batches, so that they can be written with `Write::write_vectored` or
+    /// an equivalent without copying large fields.
+    ///
+    /// Headers, varints and small fields are gathered in a scratch buffer,
+    /// while bytes fields of 4 KiB or more are referenced in place. Each
+    /// batch holds the scratch bytes gathered since the previous one,
+    /// followed by a large field if one was reached. The slices borrow the
+    /// message only for the duration of the call to `f`, as the values
+    /// `Serialize` implementations write may be temporaries. In
+    /// deterministic mode, the message is buffered as with `serialize`.
+    pub fn serialize_slices<T, F>(&self, msg: &T, f: F) -> io::Result<()>
+            where T: ?Sized + Serialize,
+                  F: FnMut(&[IoSlice]) -> io::Result<()> {
+        let mut out = VectoredWriter::new(f);
+        try!(self.serialize(msg, &mut out));
+        out.flush()
+    }
+
+    /// Serializes the message to `writer` like `serialize`, but writes the
+    /// slices produced by `serialize_slices` using `Write::write_vectored`,
+    /// so that large bytes fields are sent in place rather than copied.
+    pub fn serialize_vectored<T: ?Sized + Serialize, W: io::Write>(&self, msg: &T, writer: &mut W) -> io::Result<()> {
+        try!(self.serialize_slices(msg, |bufs| write_all_vectored(writer, bufs)));
+        writer.flush()
+    }
+
+    pub fn serialize_into<T: ?Sized + Serialize>(&self, msg: &T, dst: &mut [u8]) -> io::Result<()> {
         if self.size > dst.len() {
-            return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "destination buffer not large enough to contain serialized message"));
+            return Err(ErrorKind::BufferTooSmall.into());
         }
 
         self.serialize(msg, &mut io::BufWriter::new(dst))
@@ -67,6 +142,12 @@ impl Serializer {
 #[doc(hidden)]
 impl OutputStream for Serializer {
     fn write<T: ?Sized + Serialize>(&mut self, field: u32, val: &T) -> io::Result<()> {
+        try!(wire_type::validate_field(field));
+
+        if self.proto3 && val.is_default() {
+            return Ok(());
+        }
+
         val.serialize_nested(field, self)
     }
 
@@ -88,17 +169,27 @@ impl OutputStream for Serializer {
     }
 
     fn write_packed<T, I>(&mut self, field: u32, vals: I) -> io::Result<()>
-            where T: Varint,
+            where T: Packed,
                   I: IntoIterator<Item=T> {
+        let vals = vals.into_iter();
+
+        // Compute the nested size of the packed field. Fixed-width values
+        // need not be visited when the number of values is known.
+        let nested_size = match (T::fixed_len(), vals.size_hint()) {
+            (Some(len), (lower, Some(upper))) if lower == upper => lower * len,
+            _ => vals.map(Packed::packed_len).sum(),
+        };
+
+        self.nested.push(nested_size);
+        self.size += nested_size;
+
+        // Empty packed fields are omitted entirely
+        if nested_size > 0 {
+            try!(write_head(self, field, WireType::LengthDelimited));
+            try!(self.write_raw_varint(nested_size));
+        }
 
-        // Compute the nested size of the packed field
-        self.write_nested(field, |me| {
-            for val in vals {
-                try!(me.write_raw_varint(val));
-            }
-
-            Ok(())
-        })
+        Ok(())
     }
 
     fn write_raw_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
@@ -113,4 +204,8 @@ impl OutputStream for Serializer {
         self.size += len;
         Ok(())
     }
+
+    fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
 }
@@ -0,0 +1,268 @@
+use {serializer_for, Error, ErrorKind, OutputStream, Packed, Serialize, Varint, WireType};
+use wire_type;
+use std::io::{self, Read, Write};
+
+/// The size of the buffer used to copy streamed bytes.
+const COPY_BUF_LEN: usize = 8 * 1024;
+
+/// Writes a message field by field directly to a writer, without computing
+/// the size of the whole message first.
+///
+/// Unlike `serialize`, which runs `Serialize::serialize` twice, once to
+/// compute nested lengths and once to write, `StreamWriter` writes each field
+/// as it is given. Large values can be streamed from one-shot sources, such
+/// as a `Read` or an iterator that can't be cloned, as long as their encoded
+/// length is known up front or can be computed from a re-iterable source.
+/// Only the field being written, and never the full message, is held in
+/// memory.
+///
+/// ```
+/// use buffoon::StreamWriter;
+/// use std::io::Cursor;
+///
+/// let mut chunk = Cursor::new(vec![7; 100_000]);
+///
+/// let mut out = StreamWriter::new(vec![]);
+/// out.write(1, "file.bin").unwrap();
+/// out.write_bytes_from(2, 100_000, &mut chunk).unwrap();
+///
+/// let bytes = out.into_inner();
+/// assert_eq!(bytes.len(), 10 + 4 + 100_000);
+/// ```
+pub struct StreamWriter<W> {
+    writer: W,
+    written: u64,
+}
+
+impl<W: Write> StreamWriter<W> {
+    pub fn new(writer: W) -> StreamWriter<W> {
+        StreamWriter {
+            writer: writer,
+            written: 0,
+        }
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Writes a field, as `OutputStream::write` does. Only this field is
+    /// serialized in memory.
+    pub fn write<T: ?Sized + Serialize>(&mut self, field: u32, val: &T) -> io::Result<()> {
+        let msg = Single(field, val);
+        let serializer = try!(serializer_for(&msg));
+
+        serializer.serialize(&msg, self)
+    }
+
+    /// Writes every value of a repeated field. Values are serialized one at a
+    /// time, so `vals` is only iterated once and can be unbounded.
+    pub fn write_repeated<T, I>(&mut self, field: u32, vals: I) -> io::Result<()>
+            where T: Serialize,
+                  I: IntoIterator<Item=T> {
+        for val in vals {
+            try!(self.write(field, &val));
+        }
+
+        Ok(())
+    }
+
+    /// Writes a bytes field holding the next `len` bytes of `src`.
+    ///
+    /// Returns an `ErrorKind::UnexpectedEof` error if `src` ends early.
+    pub fn write_bytes_from<R: Read>(&mut self, field: u32, len: u64, src: &mut R) -> io::Result<()> {
+        try!(self.write_head(field, WireType::LengthDelimited));
+        try!(self.write_raw_varint(len));
+
+        let mut buf = [0; COPY_BUF_LEN];
+        let mut rem = len;
+
+        while rem > 0 {
+            let max = if rem < COPY_BUF_LEN as u64 { rem as usize } else { COPY_BUF_LEN };
+
+            let n = match src.read(&mut buf[..max]) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            try!(self.write_raw_bytes(&buf[..n]));
+            rem -= n as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a packed repeated field whose encoded length, in bytes, is
+    /// `len`. `vals` is only iterated once.
+    ///
+    /// The length is checked once the values are written, returning an
+    /// `ErrorKind::InvalidValue` error if it doesn't match.
+    pub fn write_packed_sized<T, I>(&mut self, field: u32, len: u64, vals: I) -> io::Result<()>
+            where T: Packed,
+                  I: IntoIterator<Item=T> {
+        self.write_nested_sized(field, len, |out| {
+            for val in vals {
+                try!(val.write_packed(out));
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Writes a packed repeated field, iterating `vals` twice: once to
+    /// compute the encoded length and once to write the values.
+    pub fn write_packed<T, I>(&mut self, field: u32, vals: I) -> io::Result<()>
+            where T: Packed,
+                  I: IntoIterator<Item=T> + Clone {
+        let len = vals.clone().into_iter().map(|val| val.packed_len() as u64).sum();
+
+        if len == 0 {
+            // As with `OutputStream::write_packed`, empty fields are omitted
+            return wire_type::validate_field(field);
+        }
+
+        self.write_packed_sized(field, len, vals)
+    }
+
+    /// Writes a nested message whose encoded length, in bytes, is `len`.
+    /// `f` writes the fields of the message.
+    ///
+    /// The length is checked once the message is written, returning an
+    /// `ErrorKind::InvalidValue` error if it doesn't match.
+    pub fn write_nested_sized<F>(&mut self, field: u32, len: u64, f: F) -> io::Result<()>
+            where F: FnOnce(&mut StreamWriter<W>) -> io::Result<()> {
+        try!(self.write_head(field, WireType::LengthDelimited));
+        try!(self.write_raw_varint(len));
+
+        let start = self.written;
+        try!(f(self));
+
+        if self.written - start != len {
+            let err = Error::new(ErrorKind::InvalidValue("streamed length does not match the declared length"));
+            return Err(err.into());
+        }
+
+        Ok(())
+    }
+
+    fn write_head(&mut self, field: u32, wire_type: WireType) -> io::Result<()> {
+        try!(wire_type::validate_field(field));
+        self.write_raw_varint((field << 3) | wire_type as u32)
+    }
+
+    fn write_raw_varint<T: Varint>(&mut self, val: T) -> io::Result<()> {
+        val.write(self)
+    }
+
+    fn write_raw_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write_all(bytes)
+    }
+}
+
+// Lets `Serializer::serialize` write fields through the byte count
+impl<W: Write> Write for StreamWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.writer.write(buf));
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A message holding a single field.
+struct Single<'a, T: ?Sized + 'a>(u32, &'a T);
+
+impl<'a, T: ?Sized + Serialize> Serialize for Single<'a, T> {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        out.write(self.0, self.1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {serialize, Error, ErrorKind, OutputStream, Serialize};
+    use super::StreamWriter;
+    use std::io::{self, Cursor};
+
+    struct Upload {
+        name: String,
+        sizes: Vec<u32>,
+        data: Vec<u8>,
+    }
+
+    impl Serialize for Upload {
+        fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+            try!(out.write(1, &self.name));
+            try!(out.write_packed(2, self.sizes.iter().cloned()));
+            try!(out.write(3, &self.data));
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_matching_serialize() {
+        let upload = Upload {
+            name: "a".to_string(),
+            sizes: vec![1, 300, 70_000],
+            data: vec![9; 20_000],
+        };
+
+        let mut out = StreamWriter::new(vec![]);
+        out.write(1, &upload.name).unwrap();
+        out.write_packed(2, upload.sizes.iter().cloned()).unwrap();
+        out.write_bytes_from(3, 20_000, &mut Cursor::new(&upload.data)).unwrap();
+
+        assert_eq!(out.written(), serialize(&upload).unwrap().len() as u64);
+        assert_eq!(out.into_inner(), serialize(&upload).unwrap());
+    }
+
+    #[test]
+    pub fn test_streaming_one_shot_sources() {
+        let mut out = StreamWriter::new(vec![]);
+
+        // The encoded length of 1..=200 is 127 * 1 + 73 * 2
+        out.write_packed_sized(1, 273, 1..201u32).unwrap();
+        out.write_repeated(2, (0..3u32).map(|v| v * 2)).unwrap();
+
+        let bytes = out.into_inner();
+
+        assert_eq!(&bytes[..3], b"\x0A\x91\x02");
+        assert_eq!(bytes.len(), 3 + 273 + 6);
+        assert_eq!(&bytes[276..], b"\x10\x00\x10\x02\x10\x04");
+    }
+
+    #[test]
+    pub fn test_streaming_nested_message() {
+        let mut out = StreamWriter::new(vec![]);
+
+        out.write_nested_sized(4, 5, |out| {
+            try!(out.write(1, &1u32));
+            out.write_bytes_from(2, 1, &mut &b"xyz"[..])
+        }).unwrap();
+
+        assert_eq!(out.into_inner(), b"\x22\x05\x08\x01\x12\x01x");
+    }
+
+    #[test]
+    pub fn test_rejecting_wrong_lengths() {
+        let mut out = StreamWriter::new(vec![]);
+
+        let err = out.write_packed_sized(1, 1, vec![300u32]).unwrap_err();
+        assert_eq!(Error::from_io(&err).unwrap().kind(),
+                   ErrorKind::InvalidValue("streamed length does not match the declared length"));
+
+        let err = out.write_bytes_from(2, 10, &mut &b"short"[..]).unwrap_err();
+        assert_eq!(Error::from_io(&err).unwrap().kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -3,6 +3,8 @@ use std::{cmp, io};
 pub struct Take<T> {
     inner: T,
     limit: u64,
+    pos: u64,
+    record: Option<Vec<u8>>,
 }
 
 impl<T> Take<T> {
@@ -10,6 +12,8 @@ impl<T> Take<T> {
         Take {
             inner: inner,
             limit: limit,
+            pos: 0,
+            record: None,
         }
     }
 
@@ -23,6 +27,15 @@ impl<T> Take<T> {
     pub fn limit(&self) -> u64 { self.limit }
 
     pub fn set_limit(&mut self, limit: u64) { self.limit = limit }
+
+    /// Returns the number of bytes read through this instance.
+    pub fn position(&self) -> u64 { self.pos }
+
+    /// Starts keeping a copy of the bytes read through this instance.
+    pub fn start_recording(&mut self) { self.record = Some(Vec::new()) }
+
+    /// Stops recording and returns the bytes read since `start_recording`.
+    pub fn stop_recording(&mut self) -> Vec<u8> { self.record.take().unwrap_or_default() }
 }
 
 impl<T: io::Read> io::Read for Take<T> {
@@ -35,6 +48,12 @@ impl<T: io::Read> io::Read for Take<T> {
         let max = cmp::min(buf.len() as u64, self.limit) as usize;
         let n = try!(self.inner.read(&mut buf[..max]));
         self.limit -= n as u64;
+        self.pos += n as u64;
+
+        if let Some(ref mut record) = self.record {
+            record.extend_from_slice(&buf[..n]);
+        }
+
         Ok(n)
     }
 }
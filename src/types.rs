@@ -1,7 +1,18 @@
-use {Deserialize, Field, InputStream, OutputStream, Serialize};
+use {Deserialize, Error, ErrorKind, Field, InputStream, Merge, Occurrences, OutputStream, Serialize};
+use error::annotate;
+use input_stream;
+use output_stream::{fixed32_bytes, fixed64_bytes};
+use well_known::Wrapped;
+use wire_type::WireType;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hash};
 use std::io;
+use std::rc::Rc;
+use std::sync::Arc;
 
-impl<'a, T: 'a + Serialize> Serialize for &'a T {
+impl<'a, T: ?Sized + 'a + Serialize> Serialize for &'a T {
     fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
         (*self).serialize(out)
     }
@@ -9,6 +20,192 @@ impl<'a, T: 'a + Serialize> Serialize for &'a T {
     fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
         (*self).serialize_nested(field, out)
     }
+
+    fn is_default(&self) -> bool {
+        (*self).is_default()
+    }
+}
+
+/*
+ *
+ * ===== Scalars =====
+ *
+ * A scalar serialized on its own, rather than as a field, is encoded as its
+ * wrapper message, as `google.protobuf.*Value` messages are: the value is
+ * written in field 1, omitted when it is the default.
+ *
+ */
+
+fn serialize_wrapped<T: ?Sized + Serialize, O: OutputStream>(val: &T, out: &mut O) -> io::Result<()> {
+    Wrapped(val).serialize(out)
+}
+
+fn deserialize_wrapped<T: Merge + Default, R: io::Read>(i: &mut InputStream<R>) -> io::Result<T> {
+    let mut ret = T::default();
+    try!(merge_wrapped(&mut ret, i));
+    Ok(ret)
+}
+
+/// Replaces `dst` with the value in field 1, if any.
+fn merge_wrapped<T: Deserialize, R: io::Read>(dst: &mut T, i: &mut InputStream<R>) -> io::Result<()> {
+    while let Some(f) = try!(i.read_field()) {
+        match f.tag() {
+            1 => *dst = try!(f.read()),
+            _ => try!(f.skip()),
+        }
+    }
+
+    Ok(())
+}
+
+// Scalars are replaced when merged, whether they are read from a field or
+// from their wrapper message.
+macro_rules! impl_scalar_merge {
+    ($Ty:ty) => {
+        impl Merge for $Ty {
+            fn merge_from<R: io::Read>(&mut self, input: &mut InputStream<R>) -> io::Result<()> {
+                merge_wrapped(self, input)
+            }
+
+            fn merge_nested<R: io::Read>(&mut self, field: Field<R>) -> io::Result<()> {
+                *self = try!(Self::deserialize_nested(field));
+                Ok(())
+            }
+        }
+    }
+}
+
+/*
+ *
+ * ===== Smart pointers =====
+ *
+ * Pointers are encoded as the value they point to. `Box` makes recursive
+ * messages, such as trees, possible.
+ *
+ */
+
+macro_rules! impl_pointer {
+    ($Ptr:ident, $get_mut:path, $($bound:tt)*) => {
+        impl<T: ?Sized + Serialize> Serialize for $Ptr<T> {
+            fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+                (**self).serialize(out)
+            }
+
+            fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
+                (**self).serialize_nested(field, out)
+            }
+
+            fn is_default(&self) -> bool {
+                (**self).is_default()
+            }
+        }
+
+        impl<T: Deserialize> Deserialize for $Ptr<T> {
+            fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+                T::deserialize(i).map($Ptr::new)
+            }
+
+            fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Self> {
+                T::deserialize_nested(field).map($Ptr::new)
+            }
+
+            fn occurrences<'a, R: io::Read + 'a>() -> Option<Box<dyn Occurrences<Self, R> + 'a>> where Self: 'a {
+                T::occurrences().map(|inner| {
+                    Box::new(Mapped { inner: inner, map: $Ptr::new }) as Box<dyn Occurrences<Self, R>>
+                })
+            }
+        }
+
+        impl<T: Merge $($bound)*> Merge for $Ptr<T> {
+            fn merge_from<R: io::Read>(&mut self, input: &mut InputStream<R>) -> io::Result<()> {
+                $get_mut(self).merge_from(input)
+            }
+
+            fn merge_nested<R: io::Read>(&mut self, field: Field<R>) -> io::Result<()> {
+                $get_mut(self).merge_nested(field)
+            }
+        }
+    }
+}
+
+/// Wraps the value collected from the occurrences of a repeated field.
+struct Mapped<'a, T, U, R> {
+    inner: Box<dyn Occurrences<T, R> + 'a>,
+    map: fn(T) -> U,
+}
+
+impl<'a, T, U, R: io::Read> Occurrences<U, R> for Mapped<'a, T, U, R> {
+    fn read(&mut self, field: Field<R>) -> io::Result<()> {
+        self.inner.read(field)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<U> {
+        let Mapped { inner, map } = *self;
+        inner.finish().map(map)
+    }
+}
+
+fn box_get_mut<T>(val: &mut Box<T>) -> &mut T {
+    val
+}
+
+impl_pointer! { Box, box_get_mut, }
+// Shared values are cloned before being merged into, unless not shared
+impl_pointer! { Rc, Rc::make_mut, + Clone }
+impl_pointer! { Arc, Arc::make_mut, + Clone }
+
+macro_rules! impl_boxed_slice {
+    ($Ty:ty, $Owned:ty, $into_boxed:path) => {
+        impl Deserialize for Box<$Ty> {
+            fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+                <$Owned>::deserialize(i).map($into_boxed)
+            }
+
+            fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Self> {
+                <$Owned>::deserialize_nested(field).map($into_boxed)
+            }
+        }
+
+        impl_scalar_merge! { Box<$Ty> }
+    }
+}
+
+impl_boxed_slice! { str, String, String::into_boxed_str }
+impl_boxed_slice! { [u8], Vec<u8>, Vec::into_boxed_slice }
+
+impl<'a, B: ?Sized + ToOwned + Serialize> Serialize for Cow<'a, B> {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        (**self).serialize(out)
+    }
+
+    fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
+        (**self).serialize_nested(field, out)
+    }
+
+    fn is_default(&self) -> bool {
+        (**self).is_default()
+    }
+}
+
+// Decoded values are always owned
+impl<'a, B: ?Sized + ToOwned> Deserialize for Cow<'a, B> where B::Owned: Deserialize {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+        B::Owned::deserialize(i).map(Cow::Owned)
+    }
+
+    fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Self> {
+        B::Owned::deserialize_nested(field).map(Cow::Owned)
+    }
+}
+
+impl<'a, B: ?Sized + ToOwned> Merge for Cow<'a, B> where B::Owned: Merge {
+    fn merge_from<R: io::Read>(&mut self, input: &mut InputStream<R>) -> io::Result<()> {
+        self.to_mut().merge_from(input)
+    }
+
+    fn merge_nested<R: io::Read>(&mut self, field: Field<R>) -> io::Result<()> {
+        self.to_mut().merge_nested(field)
+    }
 }
 
 /*
@@ -18,28 +215,36 @@ impl<'a, T: 'a + Serialize> Serialize for &'a T {
  */
 
 impl Serialize for [u8] {
-    fn serialize<O: OutputStream>(&self, _: &mut O) -> io::Result<()> {
-        unimplemented!();
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        serialize_wrapped(self, out)
     }
 
     fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
         out.write_bytes(field, self)
     }
+
+    fn is_default(&self) -> bool {
+        self.is_empty()
+    }
 }
 
 impl Serialize for Vec<u8> {
-    fn serialize<O: OutputStream>(&self, _: &mut O) -> io::Result<()> {
-        unimplemented!();
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        serialize_wrapped(self, out)
     }
 
     fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
         (&**self).serialize_nested(field, out)
     }
+
+    fn is_default(&self) -> bool {
+        self.is_empty()
+    }
 }
 
 impl Deserialize for Vec<u8> {
-    fn deserialize<R: io::Read>(_: &mut InputStream<R>) -> io::Result<Self> {
-        unimplemented!();
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+        deserialize_wrapped(i)
     }
 
     fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Vec<u8>> {
@@ -47,57 +252,97 @@ impl Deserialize for Vec<u8> {
     }
 }
 
+impl_scalar_merge! { Vec<u8> }
+
 impl Serialize for str {
-    fn serialize<O: OutputStream>(&self, _: &mut O) -> io::Result<()> {
-        unimplemented!();
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        serialize_wrapped(self, out)
     }
 
     fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
         out.write_bytes(field, self.as_bytes())
     }
+
+    fn is_default(&self) -> bool {
+        self.is_empty()
+    }
 }
 
 impl Serialize for String {
-    fn serialize<O: OutputStream>(&self, _: &mut O) -> io::Result<()> {
-        unimplemented!();
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        serialize_wrapped(self, out)
     }
 
     fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
         (&**self).serialize_nested(field, out)
     }
+
+    fn is_default(&self) -> bool {
+        self.is_empty()
+    }
 }
 
 impl Deserialize for String {
-    fn deserialize<R: io::Read>(_: &mut InputStream<R>) -> io::Result<Self> {
-        unimplemented!();
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+        deserialize_wrapped(i)
     }
 
     fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<String> {
         match String::from_utf8(try!(field.read_bytes())) {
             Ok(s) => Ok(s),
-            Err(_) => Err(unexpected_output("string not UTF-8 encoded"))
+            Err(_) => Err(ErrorKind::InvalidUtf8.into())
         }
     }
 }
 
+impl_scalar_merge! { String }
+
 impl Serialize for bool {
-    fn serialize<O: OutputStream>(&self, _: &mut O) -> io::Result<()> {
-        unimplemented!();
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        serialize_wrapped(self, out)
     }
 
     fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
         (if *self { 1u32 } else { 0u32 }).serialize_nested(field, out)
     }
+
+    fn is_default(&self) -> bool {
+        !*self
+    }
 }
 
 impl Deserialize for bool {
-    fn deserialize<R: io::Read>(_: &mut InputStream<R>) -> io::Result<Self> {
-        unimplemented!();
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+        deserialize_wrapped(i)
     }
 
     fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Self> {
         u32::deserialize_nested(field).map(|v| v != 0)
     }
+
+    fn deserialize_repeated<R: io::Read>(field: Field<R>, dst: &mut Vec<Self>) -> io::Result<()> {
+        field.read_repeated(dst)
+    }
+}
+
+impl_scalar_merge! { bool }
+
+impl Packed for bool {
+    fn packed_len(self) -> usize {
+        1
+    }
+
+    fn write_packed<W: io::Write>(self, dst: &mut W) -> io::Result<()> {
+        (self as u32).write(dst)
+    }
+
+    fn read_packed<R: io::Read>(input: &mut InputStream<R>) -> io::Result<Option<Self>> {
+        Ok(try!(input.read_varint::<u64>()).map(|v| v != 0))
+    }
+
+    fn wire_type() -> WireType {
+        WireType::Varint
+    }
 }
 
 /*
@@ -107,8 +352,11 @@ impl Deserialize for bool {
  */
 
 impl<T: Serialize> Serialize for Option<T> {
-    fn serialize<O: OutputStream>(&self, _: &mut O) -> io::Result<()> {
-        unimplemented!();
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        match *self {
+            Some(ref val) => val.serialize(out),
+            None => Ok(()),
+        }
     }
 
     fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
@@ -120,184 +368,1069 @@ impl<T: Serialize> Serialize for Option<T> {
     }
 }
 
-/*
- *
- * ===== Tuples =====
- *
- */
-
-impl<T1, T2> Serialize for (T1, T2)
-        where T1: Serialize,
-              T2: Serialize {
+impl<T: Deserialize> Deserialize for Option<T> {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+        T::deserialize(i).map(Some)
+    }
 
-    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
-        try!(out.write(1, &self.0));
-        try!(out.write(2, &self.1));
-        Ok(())
+    fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Self> {
+        T::deserialize_nested(field).map(Some)
     }
 }
 
-impl<T1, T2> Deserialize for (T1, T2)
-        where T1: Deserialize,
-              T2: Deserialize {
+impl<T: Merge> Merge for Option<T> {
+    fn merge_from<R: io::Read>(&mut self, input: &mut InputStream<R>) -> io::Result<()> {
+        if let Some(ref mut val) = *self {
+            return val.merge_from(input);
+        }
 
-    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
-        let mut a = None;
-        let mut b = None;
+        *self = Some(try!(T::deserialize(input)));
+        Ok(())
+    }
 
-        while let Some(f) = try!(i.read_field()) {
-            match f.tag() {
-                1 => a = Some(try!(f.read())),
-                2 => b = Some(try!(f.read())),
-                _ => try!(f.skip()),
-            }
+    fn merge_nested<R: io::Read>(&mut self, field: Field<R>) -> io::Result<()> {
+        if let Some(ref mut val) = *self {
+            return val.merge_nested(field);
         }
 
-        Ok((required!(a, "tuple::0"), required!(b, "tuple::1")))
+        *self = Some(try!(T::deserialize_nested(field)));
+        Ok(())
     }
 }
 
 /*
  *
- * ===== Varint =====
+ * ===== Maps =====
+ *
+ * Map fields are encoded as repeated entries holding the key in field 1 and
+ * the value in field 2. A map serialized on its own is encoded as a message
+ * holding the entries in field 1.
  *
  */
 
-/// Trait for values that can be serialized / deserialized as varints
-pub trait Varint: Sized {
-    #[doc(hidden)]
-    fn wire_len(self) -> usize;
-
-    #[doc(hidden)]
-    fn write<W: io::Write>(self, dst: &mut W) -> io::Result<()>;
+impl<K, V, S> Serialize for HashMap<K, V, S>
+        where K: Serialize + Hash + Eq,
+              V: Serialize,
+              S: BuildHasher {
 
-    #[doc(hidden)]
-    fn read<R: io::Read>(src: &mut R) -> io::Result<Option<Self>>;
-}
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        self.serialize_nested(1, out)
+    }
 
-macro_rules! impl_unsigned {
-    ($Ty:ty) => {
-        impl Serialize for $Ty {
-            fn serialize<O: OutputStream>(&self, _: &mut O) -> io::Result<()> {
-                unimplemented!();
-            }
+    fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
+        // Iteration order is arbitrary, so sort the entries when the output
+        // must be byte-stable
+        if out.is_deterministic() {
+            let mut entries = Vec::with_capacity(self.len());
 
-            fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
-                out.write_varint(field, *self)
+            for (k, v) in self {
+                entries.push((try!(key_order(k)), Entry { key: k, value: v }));
             }
-        }
 
-        impl Deserialize for $Ty {
-            fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
-                match try!(i.read_varint()) {
-                    Some(v) => Ok(v),
-                    None => Err(eof()),
-                }
-            }
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
 
-            fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Self> {
-                field.read_varint()
-            }
+            return out.write_repeated(field, entries.iter().map(|e| &e.1));
         }
 
-        impl Varint for $Ty {
-            #[inline]
-            fn wire_len(self) -> usize {
-                let mut num = self as u64;
-                let mut len = 0;
+        out.write_repeated(field, self.iter().map(|(k, v)| Entry { key: k, value: v }))
+    }
+}
 
-                // Handle a common case
-                if num & (!0 << 7) == 0 {
-                    return 1;
-                }
+impl<K, V, S> Deserialize for HashMap<K, V, S>
+        where K: Deserialize + Default + Hash + Eq,
+              V: Deserialize + Default,
+              S: BuildHasher + Default {
 
-                let mut n = 2;
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+        let mut map = HashMap::default();
+        try!(map.merge_from(i));
+        Ok(map)
+    }
 
-                if num & (!0 << 35) != 0 {
-                    n += 4;
-                    num >>= 28;
-                }
+    fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Self> {
+        let mut map = HashMap::default();
+        try!(map.merge_nested(field));
+        Ok(map)
+    }
+}
 
-                if num & (!0 << 21) != 0 {
-                    n += 2;
-                    num >> 14;
-                }
+impl<K, V, S> Merge for HashMap<K, V, S>
+        where K: Deserialize + Default + Hash + Eq,
+              V: Deserialize + Default,
+              S: BuildHasher + Default {
 
-                if num & (!0 << 14) != 0 {
-                    n += 1;
-                }
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        merge_entries(i, |k, v| { self.insert(k, v); })
+    }
 
-                len += n;
-                len
-            }
+    fn merge_nested<R: io::Read>(&mut self, field: Field<R>) -> io::Result<()> {
+        let entry: Entry<K, V> = try!(field.read());
+        self.insert(entry.key, entry.value);
+        Ok(())
+    }
+}
 
-            #[inline]
-            fn write<W: io::Write>(mut self, dst: &mut W) -> io::Result<()> {
-                loop {
-                    // Grab up to 7 bits of the number
-                    let bits = (self & 0x7f) as u8;
+impl<K, V> Serialize for BTreeMap<K, V>
+        where K: Serialize + Ord,
+              V: Serialize {
 
-                    // Shift the remaining bits
-                    self >>= 7;
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        self.serialize_nested(1, out)
+    }
 
-                    if self == 0 {
-                        try!(dst.write_all(&[bits]));
-                        return Ok(());
-                    }
+    fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
+        out.write_repeated(field, self.iter().map(|(k, v)| Entry { key: k, value: v }))
+    }
+}
 
-                    try!(dst.write_all(&[bits | 0x80]));
-                }
-            }
+impl<K, V> Deserialize for BTreeMap<K, V>
+        where K: Deserialize + Default + Ord,
+              V: Deserialize + Default {
 
-            #[inline]
-            fn read<R: io::Read>(src: &mut R) -> io::Result<Option<$Ty>> {
-                let mut ret = 0;
-                let mut shift = 0;
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+        let mut map = BTreeMap::new();
+        try!(map.merge_from(i));
+        Ok(map)
+    }
+
+    fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Self> {
+        let mut map = BTreeMap::new();
+        try!(map.merge_nested(field));
+        Ok(map)
+    }
+}
 
-                let mut buf = [0; 1];
+impl<K, V> Merge for BTreeMap<K, V>
+        where K: Deserialize + Default + Ord,
+              V: Deserialize + Default {
 
-                loop {
-                    match src.read(&mut buf) {
-                        Ok(0) => break,
-                        Ok(_) => {
-                            let byte = buf[0];
-                            let bits = (byte & 0x7f) as $Ty;
-
-                            // TODO: Handle overflow
-                            ret |= bits << shift;
-                            shift += 7;
-
-                            if !has_msb(byte) {
-                                return Ok(Some(ret));
-                            }
-                        }
-                        Err(e) => return Err(e),
-                    }
-                }
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        merge_entries(i, |k, v| { self.insert(k, v); })
+    }
 
-                match shift {
-                    0 => Ok(None),
-                    _ => Err(eof()),
-                }
-            }
-        }
+    fn merge_nested<R: io::Read>(&mut self, field: Field<R>) -> io::Result<()> {
+        let entry: Entry<K, V> = try!(field.read());
+        self.insert(entry.key, entry.value);
+        Ok(())
     }
 }
 
-impl_unsigned! { u8 }
-impl_unsigned! { u16 }
-impl_unsigned! { u32 }
-impl_unsigned! { u64 }
-impl_unsigned! { usize }
+/// A single map entry. Keys and values missing from an encoded entry are
+/// decoded as their default value.
+struct Entry<K, V> {
+    key: K,
+    value: V,
+}
 
-fn has_msb(byte: u8) -> bool {
-    byte & 0x80 != 0
+impl<K: Serialize, V: Serialize> Serialize for Entry<K, V> {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        // Entries always hold both their key and value, so presence is kept
+        // even when writing proto3 messages
+        try!(out.write(1, &Some(&self.key)));
+        try!(out.write(2, &Some(&self.value)));
+        Ok(())
+    }
 }
 
-fn unexpected_output(desc: &'static str) -> io::Error {
-    io::Error::new(io::ErrorKind::InvalidInput, desc)
+impl<K, V> Deserialize for Entry<K, V>
+        where K: Deserialize + Default,
+              V: Deserialize + Default {
+
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+        let mut key = None;
+        let mut value = None;
+
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => key = Some(try!(f.read())),
+                2 => value = Some(try!(f.read())),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(Entry {
+            key: key.unwrap_or_default(),
+            value: value.unwrap_or_default(),
+        })
+    }
+}
+
+/// Reads the entries of a map serialized on its own.
+fn merge_entries<K, V, R, F>(i: &mut InputStream<R>, mut insert: F) -> io::Result<()>
+        where K: Deserialize + Default,
+              V: Deserialize + Default,
+              R: io::Read,
+              F: FnMut(K, V) {
+
+    while let Some(f) = try!(i.read_field()) {
+        match f.tag() {
+            1 => {
+                let entry: Entry<K, V> = try!(f.read());
+                insert(entry.key, entry.value);
+            }
+            _ => try!(f.skip()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the bytes that order map entries by `key` in deterministic mode.
+///
+/// Keys are compared by their encoded payload, with varints widened to
+/// big-endian, so strings sort lexicographically and unsigned integers
+/// numerically without requiring `K: Ord`.
+fn key_order<K: Serialize>(key: &K) -> io::Result<Vec<u8>> {
+    // Only the key is written
+    let bytes = try!(::serialize(&Entry { key: key, value: None::<u32> }));
+    let mut input = input_stream::from(&bytes[..]);
+
+    let field = match try!(input.read_field()) {
+        Some(field) => field,
+        None => return Ok(bytes),
+    };
+
+    match field.wire_type() {
+        WireType::Varint => {
+            let val: u64 = try!(field.read_varint());
+            Ok((0..8).rev().map(|i| (val >> (i * 8)) as u8).collect())
+        }
+        WireType::LengthDelimited => field.read_bytes(),
+        _ => Ok(bytes),
+    }
+}
+
+/*
+ *
+ * ===== Tuples =====
+ *
+ */
+
+// Element N of a tuple is encoded in field N + 1. Every element is required
+// when decoding.
+macro_rules! impl_tuple {
+    ($($T:ident $var:ident $idx:tt $tag:tt),+) => {
+        impl<$($T),+> Serialize for ($($T,)+)
+                where $($T: Serialize),+ {
+
+            fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+                $(try!(out.write($tag, &self.$idx));)+
+                Ok(())
+            }
+        }
+
+        impl<$($T),+> Deserialize for ($($T,)+)
+                where $($T: Deserialize),+ {
+
+            fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+                $(let mut $var = Element::new();)+
+
+                while let Some(f) = try!(i.read_field()) {
+                    match f.tag() {
+                        $($tag => try!($var.read(f)),)+
+                        _ => try!(f.skip()),
+                    }
+                }
+
+                Ok(($(required!(try!($var.finish($tag)), concat!("tuple::", stringify!($idx))),)+))
+            }
+        }
+
+        impl<$($T),+> Merge for ($($T,)+)
+                where $($T: Merge),+ {
+
+            fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+                while let Some(f) = try!(i.read_field()) {
+                    match f.tag() {
+                        $($tag => try!(f.merge_into(&mut self.$idx)),)+
+                        _ => try!(f.skip()),
+                    }
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The occurrences of the field holding a tuple element. Elements are read
+/// from the last occurrence of their field, except for repeated values, such
+/// as arrays, which are read from every occurrence.
+struct Element<'a, T, R> {
+    val: Option<T>,
+    occurrences: Option<Box<dyn Occurrences<T, R> + 'a>>,
+    offset: Option<u64>,
+}
+
+impl<'a, T: Deserialize + 'a, R: io::Read + 'a> Element<'a, T, R> {
+    fn new() -> Element<'a, T, R> {
+        Element {
+            val: None,
+            occurrences: T::occurrences(),
+            offset: None,
+        }
+    }
+
+    fn read(&mut self, f: Field<R>) -> io::Result<()> {
+        let occurrences = match self.occurrences {
+            Some(ref mut occurrences) => occurrences,
+            None => {
+                self.val = Some(try!(f.read()));
+                return Ok(());
+            }
+        };
+
+        if self.offset.is_none() {
+            self.offset = Some(f.offset());
+        }
+
+        occurrences.read(f)
+    }
+
+    fn finish(self, tag: u32) -> io::Result<Option<T>> {
+        let offset = self.offset.unwrap_or(0);
+
+        match self.occurrences {
+            Some(occurrences) => {
+                occurrences.finish()
+                    .map(Some)
+                    .map_err(|e| annotate(e, Some(tag), offset))
+            }
+            None => Ok(self.val),
+        }
+    }
+}
+
+impl_tuple! { T1 v0 0 1 }
+impl_tuple! { T1 v0 0 1, T2 v1 1 2 }
+impl_tuple! { T1 v0 0 1, T2 v1 1 2, T3 v2 2 3 }
+impl_tuple! { T1 v0 0 1, T2 v1 1 2, T3 v2 2 3, T4 v3 3 4 }
+impl_tuple! { T1 v0 0 1, T2 v1 1 2, T3 v2 2 3, T4 v3 3 4, T5 v4 4 5 }
+impl_tuple! { T1 v0 0 1, T2 v1 1 2, T3 v2 2 3, T4 v3 3 4, T5 v4 4 5, T6 v5 5 6 }
+impl_tuple! { T1 v0 0 1, T2 v1 1 2, T3 v2 2 3, T4 v3 3 4, T5 v4 4 5, T6 v5 5 6, T7 v6 6 7 }
+impl_tuple! { T1 v0 0 1, T2 v1 1 2, T3 v2 2 3, T4 v3 3 4, T5 v4 4 5, T6 v5 5 6, T7 v6 6 7, T8 v7 7 8 }
+impl_tuple! { T1 v0 0 1, T2 v1 1 2, T3 v2 2 3, T4 v3 3 4, T5 v4 4 5, T6 v5 5 6, T7 v6 6 7, T8 v7 7 8, T9 v8 8 9 }
+impl_tuple! { T1 v0 0 1, T2 v1 1 2, T3 v2 2 3, T4 v3 3 4, T5 v4 4 5, T6 v5 5 6, T7 v6 6 7, T8 v7 7 8, T9 v8 8 9, T10 v9 9 10 }
+impl_tuple! { T1 v0 0 1, T2 v1 1 2, T3 v2 2 3, T4 v3 3 4, T5 v4 4 5, T6 v5 5 6, T7 v6 6 7, T8 v7 7 8, T9 v8 8 9, T10 v9 9 10, T11 v10 10 11 }
+impl_tuple! { T1 v0 0 1, T2 v1 1 2, T3 v2 2 3, T4 v3 3 4, T5 v4 4 5, T6 v5 5 6, T7 v6 6 7, T8 v7 7 8, T9 v8 8 9, T10 v9 9 10, T11 v10 10 11, T12 v11 11 12 }
+
+/*
+ *
+ * ===== Arrays =====
+ *
+ * An array written as a field is a repeated field, each element being its own
+ * occurrence of the field. An array serialized on its own is encoded as a
+ * message holding the elements in field 1. Decoding fails unless exactly `N`
+ * elements are read.
+ *
+ * Elements are read with `Deserialize::deserialize_repeated`, so arrays of
+ * scalars that can be packed also accept packed fields. Tuples holding arrays
+ * read every occurrence of the array's field. `Field::read` only sees a single
+ * occurrence, so messages holding an array field gather its elements with
+ * `Field::read_repeated` instead.
+ *
+ */
+
+impl<T: Serialize, const N: usize> Serialize for [T; N] {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        self.serialize_nested(1, out)
+    }
+
+    fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
+        out.write_repeated(field, self.iter())
+    }
+}
+
+impl<T: Deserialize, const N: usize> Deserialize for [T; N] {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+        let mut vals = Vec::with_capacity(N);
+
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => {
+                    try!(T::deserialize_repeated(f, &mut vals));
+
+                    if vals.len() > N {
+                        return Err(wrong_array_len());
+                    }
+                }
+                _ => try!(f.skip()),
+            }
+        }
+
+        into_array(vals)
+    }
+
+    /// Reads the array from a single occurrence of its field, which only
+    /// holds every element if it is packed, or if `N` is 1.
+    fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Self> {
+        let mut vals = Vec::with_capacity(N);
+        try!(T::deserialize_repeated(field, &mut vals));
+        into_array(vals)
+    }
+
+    fn occurrences<'a, R: io::Read + 'a>() -> Option<Box<dyn Occurrences<Self, R> + 'a>> where Self: 'a {
+        Some(Box::new(ArrayOccurrences(Vec::with_capacity(N))))
+    }
+}
+
+/// The elements read from the occurrences of an array's field.
+struct ArrayOccurrences<T>(Vec<T>);
+
+impl<T: Deserialize, R: io::Read, const N: usize> Occurrences<[T; N], R> for ArrayOccurrences<T> {
+    fn read(&mut self, field: Field<R>) -> io::Result<()> {
+        // The array is already too long, so only the length matters
+        if self.0.len() > N {
+            return field.skip();
+        }
+
+        T::deserialize_repeated(field, &mut self.0)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<[T; N]> {
+        into_array(self.0)
+    }
+}
+
+fn into_array<T, const N: usize>(vals: Vec<T>) -> io::Result<[T; N]> {
+    <[T; N]>::try_from(vals).map_err(|_| wrong_array_len())
+}
+
+fn wrong_array_len() -> io::Error {
+    Error::new(ErrorKind::InvalidValue("wrong number of array elements")).into()
+}
+
+/*
+ *
+ * ===== Varint =====
+ *
+ */
+
+/// Trait for values that can be serialized / deserialized as varints
+pub trait Varint: Sized {
+    #[doc(hidden)]
+    fn wire_len(self) -> usize;
+
+    #[doc(hidden)]
+    fn write<W: io::Write>(self, dst: &mut W) -> io::Result<()>;
+
+    /// Strictly reads a varint, rejecting encodings that are over-long or
+    /// that do not fit in `Self`.
+    #[doc(hidden)]
+    fn read<R: io::Read>(src: &mut R) -> io::Result<Option<Self>> {
+        match try!(read_u64(src, false)) {
+            Some(val) => Self::from_u64(val, false).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Converts a decoded varint to `Self`. When `lenient` is set, values that
+    /// do not fit are truncated the same way protoc does, otherwise they are
+    /// rejected.
+    #[doc(hidden)]
+    fn from_u64(val: u64, lenient: bool) -> io::Result<Self>;
+}
+
+/*
+ *
+ * ===== Packed =====
+ *
+ */
+
+/// Trait for scalar values that can be written in packed repeated fields
+pub trait Packed: Sized {
+    /// Returns the number of bytes the value occupies in a packed field.
+    #[doc(hidden)]
+    fn packed_len(self) -> usize;
+
+    #[doc(hidden)]
+    fn write_packed<W: io::Write>(self, dst: &mut W) -> io::Result<()>;
+
+    /// Reads the next value of a packed field, returning `Ok(None)` at the
+    /// end of the field.
+    #[doc(hidden)]
+    fn read_packed<R: io::Read>(input: &mut InputStream<R>) -> io::Result<Option<Self>>;
+
+    /// Returns the wire type of a value written outside of a packed field.
+    #[doc(hidden)]
+    fn wire_type() -> WireType;
+
+    /// Returns the number of bytes every value occupies, for fixed-width
+    /// encodings.
+    #[doc(hidden)]
+    fn fixed_len() -> Option<usize> {
+        None
+    }
+}
+
+impl<T: Varint> Packed for T {
+    fn packed_len(self) -> usize {
+        self.wire_len()
+    }
+
+    fn write_packed<W: io::Write>(self, dst: &mut W) -> io::Result<()> {
+        self.write(dst)
+    }
+
+    fn read_packed<R: io::Read>(input: &mut InputStream<R>) -> io::Result<Option<Self>> {
+        input.read_varint()
+    }
+
+    fn wire_type() -> WireType {
+        WireType::Varint
+    }
+}
+
+macro_rules! impl_varint_value {
+    ($Ty:ty) => {
+        impl Serialize for $Ty {
+            fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+                serialize_wrapped(self, out)
+            }
+
+            fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
+                out.write_varint(field, *self)
+            }
+
+            fn is_default(&self) -> bool {
+                *self == 0
+            }
+        }
+
+        impl Deserialize for $Ty {
+            fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+                deserialize_wrapped(i)
+            }
+
+            fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Self> {
+                field.read_varint()
+            }
+
+            fn deserialize_repeated<R: io::Read>(field: Field<R>, dst: &mut Vec<Self>) -> io::Result<()> {
+                field.read_repeated(dst)
+            }
+        }
+
+        impl_scalar_merge! { $Ty }
+
+    }
+}
+
+macro_rules! impl_unsigned {
+    ($Ty:ty) => {
+        impl_varint_value! { $Ty }
+
+        impl Varint for $Ty {
+            #[inline]
+            fn wire_len(self) -> usize {
+                let num = self as u64;
+
+                // Handle a common case
+                if num & (!0 << 7) == 0 {
+                    return 1;
+                }
+
+                // Each byte on the wire carries 7 bits of the value
+                let bits = 64 - num.leading_zeros() as usize;
+                (bits - 1) / 7 + 1
+            }
+
+            #[inline]
+            fn write<W: io::Write>(mut self, dst: &mut W) -> io::Result<()> {
+                loop {
+                    // Grab up to 7 bits of the number
+                    let bits = (self & 0x7f) as u8;
+
+                    // Shift the remaining bits
+                    self >>= 7;
+
+                    if self == 0 {
+                        try!(dst.write_all(&[bits]));
+                        return Ok(());
+                    }
+
+                    try!(dst.write_all(&[bits | 0x80]));
+                }
+            }
+
+            #[inline]
+            fn from_u64(val: u64, lenient: bool) -> io::Result<$Ty> {
+                if !lenient && val > <$Ty>::MAX as u64 {
+                    return Err(ErrorKind::VarintOutOfRange.into());
+                }
+
+                Ok(val as $Ty)
+            }
+        }
+    }
+}
+
+impl_unsigned! { u8 }
+impl_unsigned! { u16 }
+impl_unsigned! { u32 }
+impl_unsigned! { u64 }
+impl_unsigned! { usize }
+
+// Signed integers use the `int32` / `int64` encoding: the two's complement
+// value is sign-extended to 64 bits, so negative values always take 10 bytes.
+macro_rules! impl_signed {
+    ($Ty:ty) => {
+        impl_varint_value! { $Ty }
+
+        impl Varint for $Ty {
+            #[inline]
+            fn wire_len(self) -> usize {
+                (self as i64 as u64).wire_len()
+            }
+
+            #[inline]
+            fn write<W: io::Write>(self, dst: &mut W) -> io::Result<()> {
+                (self as i64 as u64).write(dst)
+            }
+
+            #[inline]
+            fn from_u64(val: u64, lenient: bool) -> io::Result<$Ty> {
+                let val = val as i64;
+
+                if !lenient && (val < <$Ty>::MIN as i64 || val > <$Ty>::MAX as i64) {
+                    return Err(ErrorKind::VarintOutOfRange.into());
+                }
+
+                Ok(val as $Ty)
+            }
+        }
+    }
+}
+
+impl_signed! { i32 }
+impl_signed! { i64 }
+
+/*
+ *
+ * ===== Floats =====
+ *
+ */
+
+macro_rules! impl_float {
+    ($Ty:ty, $len:expr, $wire_type:ident, $write:ident, $read:ident, $bytes:ident) => {
+        impl Serialize for $Ty {
+            fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+                serialize_wrapped(self, out)
+            }
+
+            fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
+                out.$write(field, self.to_bits())
+            }
+
+            fn is_default(&self) -> bool {
+                // -0.0 is not the default, matching protoc
+                self.to_bits() == 0
+            }
+        }
+
+        impl Deserialize for $Ty {
+            fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+                deserialize_wrapped(i)
+            }
+
+            fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Self> {
+                field.$read().map(<$Ty>::from_bits)
+            }
+
+            fn deserialize_repeated<R: io::Read>(field: Field<R>, dst: &mut Vec<Self>) -> io::Result<()> {
+                field.read_repeated(dst)
+            }
+        }
+
+        impl_scalar_merge! { $Ty }
+
+        impl Packed for $Ty {
+            fn packed_len(self) -> usize {
+                $len
+            }
+
+            fn write_packed<W: io::Write>(self, dst: &mut W) -> io::Result<()> {
+                dst.write_all(&$bytes(self.to_bits()))
+            }
+
+            fn read_packed<R: io::Read>(input: &mut InputStream<R>) -> io::Result<Option<Self>> {
+                Ok(try!(input.$read()).map(<$Ty>::from_bits))
+            }
+
+            fn wire_type() -> WireType {
+                WireType::$wire_type
+            }
+
+            fn fixed_len() -> Option<usize> {
+                Some($len)
+            }
+        }
+    }
+}
+
+impl_float! { f32, 4, ThirtyTwoBit, write_fixed32, read_fixed32, fixed32_bytes }
+impl_float! { f64, 8, SixtyFourBit, write_fixed64, read_fixed64, fixed64_bytes }
+
+/*
+ *
+ * ===== Fixed-width integers =====
+ *
+ */
+
+macro_rules! impl_fixed {
+    ($(#[$attr:meta])* $Name:ident($Ty:ty), $Bits:ty, $len:expr, $wire_type:ident, $write:ident, $read:ident, $bytes:ident) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+        pub struct $Name(pub $Ty);
+
+        impl Serialize for $Name {
+            fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+                serialize_wrapped(self, out)
+            }
+
+            fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
+                out.$write(field, self.0 as $Bits)
+            }
+
+            fn is_default(&self) -> bool {
+                self.0 == 0
+            }
+        }
+
+        impl Deserialize for $Name {
+            fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+                deserialize_wrapped(i)
+            }
+
+            fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Self> {
+                field.$read().map(|v| $Name(v as $Ty))
+            }
+
+            fn deserialize_repeated<R: io::Read>(field: Field<R>, dst: &mut Vec<Self>) -> io::Result<()> {
+                field.read_repeated(dst)
+            }
+        }
+
+        impl_scalar_merge! { $Name }
+
+        impl Packed for $Name {
+            fn packed_len(self) -> usize {
+                $len
+            }
+
+            fn write_packed<W: io::Write>(self, dst: &mut W) -> io::Result<()> {
+                dst.write_all(&$bytes(self.0 as $Bits))
+            }
+
+            fn read_packed<R: io::Read>(input: &mut InputStream<R>) -> io::Result<Option<Self>> {
+                Ok(try!(input.$read()).map(|v| $Name(v as $Ty)))
+            }
+
+            fn wire_type() -> WireType {
+                WireType::$wire_type
+            }
+
+            fn fixed_len() -> Option<usize> {
+                Some($len)
+            }
+        }
+
+        impl From<$Ty> for $Name {
+            fn from(val: $Ty) -> $Name {
+                $Name(val)
+            }
+        }
+
+        impl From<$Name> for $Ty {
+            fn from(val: $Name) -> $Ty {
+                val.0
+            }
+        }
+    }
+}
+
+impl_fixed! {
+    /// A `fixed32` value, always encoded in 4 bytes
+    Fixed32(u32), u32, 4, ThirtyTwoBit, write_fixed32, read_fixed32, fixed32_bytes
+}
+
+impl_fixed! {
+    /// A `fixed64` value, always encoded in 8 bytes
+    Fixed64(u64), u64, 8, SixtyFourBit, write_fixed64, read_fixed64, fixed64_bytes
+}
+
+impl_fixed! {
+    /// An `sfixed32` value, always encoded in 4 bytes
+    SFixed32(i32), u32, 4, ThirtyTwoBit, write_fixed32, read_fixed32, fixed32_bytes
+}
+
+impl_fixed! {
+    /// An `sfixed64` value, always encoded in 8 bytes
+    SFixed64(i64), u64, 8, SixtyFourBit, write_fixed64, read_fixed64, fixed64_bytes
+}
+
+/*
+ *
+ * ===== ZigZag integers =====
+ *
+ */
+
+// The `sint32` / `sint64` encoding maps signed integers to unsigned ones so
+// that values of small magnitude, including negative ones, encode in few
+// bytes: 0 => 0, -1 => 1, 1 => 2, -2 => 3, ...
+macro_rules! impl_zigzag {
+    ($(#[$attr:meta])* $Name:ident($Ty:ty), $Unsigned:ty, $bits:expr) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+        pub struct $Name(pub $Ty);
+
+        impl $Name {
+            fn encode(self) -> $Unsigned {
+                ((self.0 << 1) ^ (self.0 >> ($bits - 1))) as $Unsigned
+            }
+
+            fn decode(val: $Unsigned) -> $Name {
+                $Name(((val >> 1) as $Ty) ^ -((val & 1) as $Ty))
+            }
+        }
+
+        impl Serialize for $Name {
+            fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+                serialize_wrapped(self, out)
+            }
+
+            fn serialize_nested<O: OutputStream>(&self, field: u32, out: &mut O) -> io::Result<()> {
+                out.write_varint(field, *self)
+            }
+
+            fn is_default(&self) -> bool {
+                self.0 == 0
+            }
+        }
+
+        impl Deserialize for $Name {
+            fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Self> {
+                deserialize_wrapped(i)
+            }
+
+            fn deserialize_nested<R: io::Read>(field: Field<R>) -> io::Result<Self> {
+                field.read_varint()
+            }
+
+            fn deserialize_repeated<R: io::Read>(field: Field<R>, dst: &mut Vec<Self>) -> io::Result<()> {
+                field.read_repeated(dst)
+            }
+        }
+
+        impl_scalar_merge! { $Name }
+
+        impl Varint for $Name {
+            #[inline]
+            fn wire_len(self) -> usize {
+                self.encode().wire_len()
+            }
+
+            #[inline]
+            fn write<W: io::Write>(self, dst: &mut W) -> io::Result<()> {
+                self.encode().write(dst)
+            }
+
+            #[inline]
+            fn from_u64(val: u64, lenient: bool) -> io::Result<$Name> {
+                <$Unsigned>::from_u64(val, lenient).map($Name::decode)
+            }
+        }
+
+        impl From<$Ty> for $Name {
+            fn from(val: $Ty) -> $Name {
+                $Name(val)
+            }
+        }
+
+        impl From<$Name> for $Ty {
+            fn from(val: $Name) -> $Ty {
+                val.0
+            }
+        }
+    }
+}
+
+impl_zigzag! {
+    /// An `sint32` value, ZigZag encoded as a varint
+    SInt32(i32), u32, 32
+}
+
+impl_zigzag! {
+    /// An `sint64` value, ZigZag encoded as a varint
+    SInt64(i64), u64, 64
+}
+
+/// The maximum number of bytes a varint may occupy on the wire.
+const MAX_VARINT_LEN: usize = 10;
+
+/// Reads a raw varint as `u64`.
+///
+/// If at EOF before reading the first byte, returns Ok(None). Varints longer
+/// than 10 bytes are always rejected. Bits beyond the 64th are rejected unless
+/// `lenient` is set, in which case they are discarded.
+pub fn read_u64<R: io::Read>(src: &mut R, lenient: bool) -> io::Result<Option<u64>> {
+    let mut ret = 0;
+    let mut len = 0;
+
+    let mut buf = [0; 1];
+
+    loop {
+        match src.read(&mut buf) {
+            Ok(0) => break,
+            Ok(_) => {
+                let byte = buf[0];
+                let bits = (byte & 0x7f) as u64;
+                let shift = 7 * len;
+
+                len += 1;
+
+                // The 10th byte only has room for the 64th bit
+                if len == MAX_VARINT_LEN && bits > 1 && !lenient {
+                    return Err(ErrorKind::MalformedVarint.into());
+                }
+
+                ret |= bits << shift;
+
+                if !has_msb(byte) {
+                    return Ok(Some(ret));
+                }
+
+                if len == MAX_VARINT_LEN {
+                    return Err(ErrorKind::MalformedVarint.into());
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            // Other errors, such as `WouldBlock`, are surfaced unchanged
+            Err(e) => return Err(e),
+        }
+    }
+
+    match len {
+        0 => Ok(None),
+        _ => Err(eof()),
+    }
+}
+
+fn has_msb(byte: u8) -> bool {
+    byte & 0x80 != 0
 }
 
 fn eof() -> io::Error {
-    return unexpected_output("unexpected EOF");
+    ErrorKind::UnexpectedEof.into()
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Cursor};
+    use {deserialize, merge, serialize};
+    use super::{Fixed64, SInt32, SInt64, Varint, read_u64};
+
+    #[test]
+    pub fn test_varint_wire_len() {
+        let nums = [0u64, 127, 128, 16_383, 16_384, 1 << 21, 1 << 28, 1 << 35, 1 << 42, 1 << 63, !0];
+
+        for &num in nums.iter() {
+            let mut bytes = vec![];
+            num.write(&mut bytes).unwrap();
+            assert_eq!(num.wire_len(), bytes.len(), "num={}", num);
+        }
+    }
+
+    #[test]
+    pub fn test_reading_max_varint() {
+        let bytes = b"\xff\xff\xff\xff\xff\xff\xff\xff\xff\x01";
+        assert_eq!(u64::read(&mut Cursor::new(&bytes[..])).unwrap(), Some(!0));
+    }
+
+    #[test]
+    pub fn test_reading_overlong_varint() {
+        let bytes = b"\x80\x80\x80\x80\x80\x80\x80\x80\x80\x80\x00";
+        let err = read_u64(&mut Cursor::new(&bytes[..]), true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    pub fn test_reading_varint_overflowing_64_bits() {
+        let bytes = b"\xff\xff\xff\xff\xff\xff\xff\xff\xff\x7f";
+        let err = read_u64(&mut Cursor::new(&bytes[..]), false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let val = read_u64(&mut Cursor::new(&bytes[..]), true).unwrap();
+        assert_eq!(val, Some(!0));
+    }
+
+    #[test]
+    pub fn test_reading_out_of_range_varint() {
+        // 2^32 does not fit in a `u8` nor a `u32`
+        let bytes = b"\x80\x80\x80\x80\x10";
+        let err = u8::read(&mut Cursor::new(&bytes[..])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        assert!(u32::read(&mut Cursor::new(&bytes[..])).is_err());
+        assert_eq!(u64::read(&mut Cursor::new(&bytes[..])).unwrap(), Some(1 << 32));
+    }
+
+    #[test]
+    pub fn test_truncating_out_of_range_varint() {
+        assert_eq!(u32::from_u64((1 << 32) | 7, true).unwrap(), 7);
+        assert_eq!(u8::from_u64(300, true).unwrap(), 44);
+    }
+
+    #[test]
+    pub fn test_zigzag_encoding() {
+        let cases = [(0i64, 0u64), (-1, 1), (1, 2), (-2, 3), (i64::MAX, !0 - 1), (i64::MIN, !0)];
+
+        for &(num, encoded) in cases.iter() {
+            let mut bytes = vec![];
+            SInt64(num).write(&mut bytes).unwrap();
+
+            let mut expect = vec![];
+            encoded.write(&mut expect).unwrap();
+
+            assert_eq!(bytes, expect, "num={}", num);
+            assert_eq!(SInt64::from_u64(encoded, false).unwrap(), SInt64(num));
+        }
+
+        assert_eq!(SInt32::from_u64(u64::from(u32::MAX), false).unwrap(), SInt32(i32::MIN));
+        assert!(SInt32::from_u64(1 << 32, false).is_err());
+    }
+
+    #[test]
+    pub fn test_serializing_top_level_scalars() {
+        assert_eq!(serialize(&"x").unwrap(), b"\x0A\x01x");
+        assert_eq!(serialize(&b"ab".to_vec()).unwrap(), b"\x0A\x02ab");
+        assert_eq!(serialize(&true).unwrap(), b"\x08\x01");
+        assert_eq!(serialize(&150u32).unwrap(), b"\x08\x96\x01");
+        assert_eq!(serialize(&1.0f32).unwrap(), b"\x0D\x00\x00\x80\x3f");
+        assert_eq!(serialize(&SInt32(-1)).unwrap(), b"\x08\x01");
+        assert_eq!(serialize(&Some(2u8)).unwrap(), b"\x08\x02");
+
+        // Defaults are omitted
+        assert!(serialize(&"").unwrap().is_empty());
+        assert!(serialize(&0u64).unwrap().is_empty());
+        assert!(serialize(&None::<u32>).unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn test_deserializing_top_level_scalars() {
+        assert_eq!(deserialize::<String, _>(&b"\x0A\x01x"[..]).unwrap(), "x");
+        assert_eq!(deserialize::<Vec<u8>, _>(&b""[..]).unwrap(), b"");
+        assert!(deserialize::<bool, _>(&b"\x08\x01"[..]).unwrap());
+        assert_eq!(deserialize::<Fixed64, _>(&b"\x09\x01\x00\x00\x00\x00\x00\x00\x00"[..]).unwrap(), Fixed64(1));
+        assert_eq!(deserialize::<Option<i32>, _>(&b"\x08\x05"[..]).unwrap(), Some(5));
+
+        // Other fields are skipped and the last value wins
+        assert_eq!(deserialize::<u32, _>(&b"\x08\x01\x12\x00\x08\x02"[..]).unwrap(), 2);
+
+        // Merging keeps the value when field 1 is missing
+        let mut val = 7u32;
+        merge(&mut val, &b""[..]).unwrap();
+        assert_eq!(val, 7);
+    }
+}
+
@@ -0,0 +1,231 @@
+use {serialize, Deserialize, ErrorKind, Field, InputStream, Merge, OutputStream, Serialize, WireType};
+use std::io::{self, Read};
+use std::slice;
+
+/// Fields of a message that were not recognized when decoding, retained so
+/// that they can be written back out or decoded later, for example as
+/// extensions.
+///
+/// Messages usually keep an `UnknownFields` member, pass unrecognized fields
+/// to `merge_field` when decoding and call `serialize` on it, with their own
+/// output stream, when encoding:
+///
+/// ```ignore
+/// match f.tag() {
+///     1 => self.name = try!(f.read()),
+///     _ => try!(self.unknown.merge_field(f)),
+/// }
+/// ```
+///
+/// Groups are skipped and not retained.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct UnknownFields {
+    fields: Vec<(u32, UnknownValue)>,
+}
+
+/// The payload of an unknown field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnknownValue {
+    Varint(u64),
+    Fixed32(u32),
+    Fixed64(u64),
+    LengthDelimited(Vec<u8>),
+}
+
+impl UnknownFields {
+    pub fn new() -> UnknownFields {
+        UnknownFields { fields: Vec::new() }
+    }
+
+    /// Returns `true` if no fields are retained.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Returns the number of fields retained.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Returns an iterator over the retained fields, in the order they were
+    /// read.
+    pub fn iter(&self) -> slice::Iter<'_, (u32, UnknownValue)> {
+        self.fields.iter()
+    }
+
+    /// Returns `true` if a field numbered `number` is retained.
+    pub fn contains(&self, number: u32) -> bool {
+        self.fields.iter().any(|&(n, _)| n == number)
+    }
+
+    /// Appends a field.
+    pub fn push(&mut self, number: u32, val: UnknownValue) {
+        self.fields.push((number, val));
+    }
+
+    /// Removes every field numbered `number`.
+    pub fn remove(&mut self, number: u32) {
+        self.fields.retain(|&(n, _)| n != number);
+    }
+
+    /// Reads the field and retains it.
+    pub fn merge_field<R: Read>(&mut self, field: Field<R>) -> io::Result<()> {
+        let number = field.tag();
+
+        if let Some(val) = try!(UnknownValue::read(field)) {
+            self.push(number, val);
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the fields numbered `number` with `action`, which is given a
+    /// stream holding only those fields.
+    pub fn read_fields<T, F>(&self, number: u32, action: F) -> io::Result<T>
+            where F: FnOnce(&mut InputStream<&[u8]>) -> io::Result<T> {
+        let bytes = try!(serialize(&Only(self, number)));
+        action(&mut InputStream::new(&bytes[..]))
+    }
+}
+
+impl Serialize for UnknownFields {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        for &(number, ref val) in &self.fields {
+            try!(val.write(number, out));
+        }
+
+        Ok(())
+    }
+}
+
+impl Deserialize for UnknownFields {
+    fn deserialize<R: Read>(i: &mut InputStream<R>) -> io::Result<UnknownFields> {
+        let mut ret = UnknownFields::new();
+        try!(ret.merge_from(i));
+        Ok(ret)
+    }
+}
+
+impl Merge for UnknownFields {
+    fn merge_from<R: Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            try!(self.merge_field(f));
+        }
+
+        Ok(())
+    }
+}
+
+impl UnknownValue {
+    /// Reads the payload of the field. Groups are skipped, returning
+    /// `Ok(None)`.
+    pub fn read<R: Read>(field: Field<R>) -> io::Result<Option<UnknownValue>> {
+        Ok(Some(match field.wire_type() {
+            WireType::Varint => UnknownValue::Varint(try!(field.read_varint())),
+            WireType::ThirtyTwoBit => UnknownValue::Fixed32(try!(field.read_fixed32())),
+            WireType::SixtyFourBit => UnknownValue::Fixed64(try!(field.read_fixed64())),
+            WireType::LengthDelimited => UnknownValue::LengthDelimited(try!(field.read_bytes())),
+            _ => {
+                try!(field.skip());
+                return Ok(None);
+            }
+        }))
+    }
+
+    /// Decodes the payload as a `T`, as if it were read from a field
+    /// declared with type `T`.
+    pub fn decode<T: Deserialize>(&self) -> io::Result<T> {
+        let bytes = try!(serialize(&Single(self)));
+        let mut input = InputStream::new(&bytes[..]);
+
+        match try!(input.read_field()) {
+            Some(f) => f.read(),
+            None => Err(ErrorKind::UnexpectedEof.into()),
+        }
+    }
+
+    fn write<O: OutputStream>(&self, number: u32, out: &mut O) -> io::Result<()> {
+        match *self {
+            UnknownValue::Varint(val) => out.write_varint(number, val),
+            UnknownValue::Fixed32(val) => out.write_fixed32(number, val),
+            UnknownValue::Fixed64(val) => out.write_fixed64(number, val),
+            UnknownValue::LengthDelimited(ref val) => out.write_bytes(number, val),
+        }
+    }
+}
+
+/// A payload encoded as field 1.
+struct Single<'a>(&'a UnknownValue);
+
+impl<'a> Serialize for Single<'a> {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        self.0.write(1, out)
+    }
+}
+
+/// The fields of an `UnknownFields` with the given number.
+struct Only<'a>(&'a UnknownFields, u32);
+
+impl<'a> Serialize for Only<'a> {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        for &(number, ref val) in self.0.iter() {
+            if number == self.1 {
+                try!(val.write(number, out));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {serialize, deserialize};
+    use super::{UnknownFields, UnknownValue};
+
+    #[test]
+    pub fn test_round_tripping_unknown_fields() {
+        let bytes = b"\x08\x96\x01\x15\x01\x00\x00\x00\x19\x02\x00\x00\x00\x00\x00\x00\x00\x22\x02hi\x08\x01";
+        let unknown: UnknownFields = deserialize(&bytes[..]).unwrap();
+
+        let fields: Vec<_> = unknown.iter().cloned().collect();
+
+        assert_eq!(fields, vec![
+            (1, UnknownValue::Varint(150)),
+            (2, UnknownValue::Fixed32(1)),
+            (3, UnknownValue::Fixed64(2)),
+            (4, UnknownValue::LengthDelimited(b"hi".to_vec())),
+            (1, UnknownValue::Varint(1)),
+        ]);
+
+        assert_eq!(serialize(&unknown).unwrap(), &bytes[..]);
+    }
+
+    #[test]
+    pub fn test_decoding_unknown_value() {
+        assert_eq!(UnknownValue::Varint(!0).decode::<i64>().unwrap(), -1);
+        assert_eq!(UnknownValue::Fixed64(0).decode::<f64>().unwrap(), 0.0);
+
+        let val = UnknownValue::LengthDelimited(b"hi".to_vec());
+        assert_eq!(val.decode::<String>().unwrap(), "hi");
+        assert!(val.decode::<u32>().is_err());
+    }
+
+    #[test]
+    pub fn test_reading_fields_by_number() {
+        let bytes = b"\x08\x01\x10\x02\x08\x03";
+        let unknown: UnknownFields = deserialize(&bytes[..]).unwrap();
+
+        let vals = unknown.read_fields(1, |i| {
+            let mut vals = vec![];
+
+            while let Some(f) = try!(i.read_field()) {
+                vals.push(try!(f.read::<u32>()));
+            }
+
+            Ok(vals)
+        }).unwrap();
+
+        assert_eq!(vals, [1, 3]);
+    }
+}
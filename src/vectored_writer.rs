@@ -0,0 +1,216 @@
+use std::cmp;
+use std::io::{self, IoSlice, Write};
+
+/// Byte slices at least this long are written in place rather than copied.
+pub const MIN_VECTORED_LEN: usize = 4 * 1024;
+
+/// The size at which the scratch buffer is flushed on its own.
+const MAX_SCRATCH_LEN: usize = 64 * 1024;
+
+/// Gathers small writes, such as field headers and varints, in a scratch
+/// buffer, and passes large slices, such as bytes fields, in place to `f`,
+/// preceded by the pending scratch bytes.
+///
+/// The large slices are only borrowed for the duration of the `write` call,
+/// so they are passed to `f` before `write` returns.
+pub struct VectoredWriter<F> {
+    scratch: Vec<u8>,
+    f: F,
+}
+
+impl<F> VectoredWriter<F>
+        where F: FnMut(&[IoSlice]) -> io::Result<()> {
+
+    pub fn new(f: F) -> VectoredWriter<F> {
+        VectoredWriter {
+            scratch: Vec::new(),
+            f: f,
+        }
+    }
+
+    /// Passes the scratch buffer followed by `bytes` to `f`, omitting empty
+    /// slices.
+    fn emit(&mut self, bytes: &[u8]) -> io::Result<()> {
+        {
+            let bufs = [IoSlice::new(&self.scratch), IoSlice::new(bytes)];
+            let start = if self.scratch.is_empty() { 1 } else { 0 };
+            let end = if bytes.is_empty() { 1 } else { 2 };
+
+            if start < end {
+                try!((self.f)(&bufs[start..end]));
+            }
+        }
+
+        self.scratch.clear();
+        Ok(())
+    }
+}
+
+impl<F> Write for VectoredWriter<F>
+        where F: FnMut(&[IoSlice]) -> io::Result<()> {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() >= MIN_VECTORED_LEN {
+            try!(self.emit(buf));
+        } else {
+            self.scratch.extend_from_slice(buf);
+
+            if self.scratch.len() >= MAX_SCRATCH_LEN {
+                try!(self.emit(&[]));
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.emit(&[])
+    }
+}
+
+/// Writes all of `bufs` to `writer` using `Write::write_vectored`, retrying
+/// partial writes.
+pub fn write_all_vectored<W: ?Sized + Write>(writer: &mut W, bufs: &[IoSlice]) -> io::Result<()> {
+    let mut bufs: Vec<&[u8]> = bufs.iter().map(|buf| &**buf).filter(|buf| !buf.is_empty()).collect();
+    let mut head = 0;
+
+    while head < bufs.len() {
+        let res = {
+            let slices: Vec<IoSlice> = bufs[head..].iter().map(|buf| IoSlice::new(buf)).collect();
+            writer.write_vectored(&slices)
+        };
+
+        let mut n = match res {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        // Skip the slices written, and the written part of the last one
+        while n > 0 {
+            let len = cmp::min(n, bufs[head].len());
+
+            bufs[head] = &bufs[head][len..];
+            n -= len;
+
+            if bufs[head].is_empty() {
+                head += 1;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use {serialize, serializer_for};
+    use super::{write_all_vectored, VectoredWriter, MIN_VECTORED_LEN};
+    use std::cmp;
+    use std::io::{self, IoSlice, Write};
+
+    // Accepts at most 1000 bytes per call, recording the slices written
+    #[derive(Default)]
+    struct Recorder {
+        bytes: Vec<u8>,
+        slices: Vec<*const u8>,
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+            let mut n = 0;
+
+            for buf in bufs {
+                let len = cmp::min(buf.len(), 1000 - n);
+
+                if len > 0 {
+                    self.slices.push(buf.as_ptr());
+                    self.bytes.extend_from_slice(&buf[..len]);
+                    n += len;
+                }
+            }
+
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_passing_large_slices_in_place() {
+        let large = vec![7; MIN_VECTORED_LEN];
+        let mut batches = vec![];
+
+        {
+            let mut out = VectoredWriter::new(|bufs: &[IoSlice]| {
+                batches.push(bufs.iter().map(|buf| (buf.as_ptr(), buf.to_vec())).collect::<Vec<_>>());
+                Ok(())
+            });
+
+            out.write_all(b"\x0A\x80\x20").unwrap();
+            out.write_all(&large).unwrap();
+            out.write_all(b"\x10\x01").unwrap();
+            out.flush().unwrap();
+        }
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0][0].1, b"\x0A\x80\x20");
+        assert_eq!(batches[0][1], (large.as_ptr(), large.clone()));
+        assert_eq!(batches[1].len(), 1);
+        assert_eq!(batches[1][0].1, b"\x10\x01");
+    }
+
+    #[test]
+    pub fn test_serializing_slices() {
+        let msg = (vec![1; MIN_VECTORED_LEN], b"trailer".to_vec());
+        let serializer = serializer_for(&msg).unwrap();
+
+        let mut bytes = vec![];
+        let mut in_place = false;
+
+        serializer.serialize_slices(&msg, |bufs| {
+            for buf in bufs {
+                in_place |= buf.as_ptr() == msg.0.as_ptr();
+                bytes.extend_from_slice(buf);
+            }
+
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(bytes, serialize(&msg).unwrap());
+        assert!(in_place);
+    }
+
+    #[test]
+    pub fn test_writing_all_vectored() {
+        let large = vec![5; 2500];
+        let mut recorder = Recorder::default();
+
+        write_all_vectored(&mut recorder, &[IoSlice::new(b"head"), IoSlice::new(&[]), IoSlice::new(&large)]).unwrap();
+
+        let mut expect = b"head".to_vec();
+        expect.extend_from_slice(&large);
+
+        assert_eq!(recorder.bytes, expect);
+        assert!(recorder.slices.contains(&large.as_ptr()));
+    }
+
+    #[test]
+    pub fn test_serializing_vectored() {
+        let msg = (b"header".to_vec(), vec![3; 3 * MIN_VECTORED_LEN]);
+        let mut recorder = Recorder::default();
+
+        let serializer = serializer_for(&msg).unwrap();
+        serializer.serialize_vectored(&msg, &mut recorder).unwrap();
+
+        assert_eq!(recorder.bytes, serialize(&msg).unwrap());
+        assert!(recorder.slices.contains(&msg.1.as_ptr()));
+    }
+}
@@ -0,0 +1,279 @@
+use {ErrorKind, InputStream, WireType};
+use std::io;
+
+/// Receives the fields of an encoded message as they are read by `visit`.
+///
+/// Every method has a default implementation ignoring the field, so visitors
+/// only implement the callbacks they care about. Returning an error stops the
+/// walk.
+pub trait Visitor {
+    /// Called for a varint field.
+    fn visit_varint(&mut self, tag: u32, val: u64) -> io::Result<()> {
+        let _ = (tag, val);
+        Ok(())
+    }
+
+    /// Called for a fixed 32 bit field.
+    fn visit_fixed32(&mut self, tag: u32, val: u32) -> io::Result<()> {
+        let _ = (tag, val);
+        Ok(())
+    }
+
+    /// Called for a fixed 64 bit field.
+    fn visit_fixed64(&mut self, tag: u32, val: u64) -> io::Result<()> {
+        let _ = (tag, val);
+        Ok(())
+    }
+
+    /// Called for a length delimited field that is not visited as a message.
+    fn visit_bytes(&mut self, tag: u32, val: &[u8]) -> io::Result<()> {
+        let _ = (tag, val);
+        Ok(())
+    }
+
+    /// Called for a length delimited field before `visit_bytes`. Returning
+    /// `true` visits the field as a nested message instead: its fields are
+    /// visited, followed by a call to `exit_message`.
+    ///
+    /// The wire format does not tell messages apart from strings and bytes,
+    /// so the visitor decides based on the tag, or by inspecting `val`.
+    fn enter_message(&mut self, tag: u32, val: &[u8]) -> io::Result<bool> {
+        let _ = (tag, val);
+        Ok(false)
+    }
+
+    /// Called for a group with its encoded contents, excluding the end group
+    /// tag.
+    fn visit_group(&mut self, tag: u32, val: &[u8]) -> io::Result<()> {
+        let _ = (tag, val);
+        Ok(())
+    }
+
+    /// Called after the fields of a nested message were visited.
+    fn exit_message(&mut self, tag: u32) -> io::Result<()> {
+        let _ = tag;
+        Ok(())
+    }
+}
+
+/// Walks the encoded message in `bytes`, passing each field to `visitor` in
+/// the order they are encoded. Length delimited values are borrowed from
+/// `bytes` rather than copied.
+pub fn visit<V: ?Sized + Visitor>(bytes: &[u8], visitor: &mut V) -> io::Result<()> {
+    // The tags of the messages being visited, with the bytes of the enclosing
+    // message following them. Nested messages are tracked here rather than by
+    // recursing, so that deeply nested input cannot overflow the stack.
+    let mut open: Vec<(u32, &[u8])> = vec![];
+    let mut rest = bytes;
+
+    loop {
+        let mut input = InputStream::new(rest);
+
+        let f = match try!(input.read_field()) {
+            Some(f) => f,
+            None => {
+                match open.pop() {
+                    Some((tag, outer)) => {
+                        try!(visitor.exit_message(tag));
+                        rest = outer;
+                        continue;
+                    }
+                    None => return Ok(()),
+                }
+            }
+        };
+
+        let tag = f.tag();
+
+        match f.wire_type() {
+            WireType::Varint => try!(visitor.visit_varint(tag, try!(f.read_varint()))),
+            WireType::ThirtyTwoBit => try!(visitor.visit_fixed32(tag, try!(f.read_fixed32()))),
+            WireType::SixtyFourBit => try!(visitor.visit_fixed64(tag, try!(f.read_fixed64()))),
+            WireType::LengthDelimited => {
+                // Only the length prefix is read, the value is borrowed
+                let len = match try!(input.read_varint::<usize>()) {
+                    Some(len) => len,
+                    None => return Err(ErrorKind::UnexpectedEof.into()),
+                };
+
+                let pos = input.position() as usize;
+
+                if len > rest.len() - pos {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+
+                let (val, next) = rest[pos..].split_at(len);
+
+                if try!(visitor.enter_message(tag, val)) {
+                    open.push((tag, next));
+                    rest = val;
+                } else {
+                    try!(visitor.visit_bytes(tag, val));
+                    rest = next;
+                }
+
+                continue;
+            }
+            WireType::StartGroup => {
+                // The contents are copied, as the end group tag can't be told
+                // apart without reading them
+                let raw = try!(f.read_raw());
+                try!(visitor.visit_group(tag, &raw.payload));
+            }
+            // Only valid when closing a group, so `skip` returns an error
+            WireType::EndGroup => try!(f.skip()),
+        }
+
+        rest = &rest[input.position() as usize..];
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use Varint;
+    use std::{cmp, io};
+    use super::{visit, Visitor};
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+    }
+
+    impl Visitor for Recorder {
+        fn visit_varint(&mut self, tag: u32, val: u64) -> io::Result<()> {
+            self.events.push(format!("varint {} {}", tag, val));
+            Ok(())
+        }
+
+        fn visit_fixed32(&mut self, tag: u32, val: u32) -> io::Result<()> {
+            self.events.push(format!("fixed32 {} {}", tag, val));
+            Ok(())
+        }
+
+        fn visit_fixed64(&mut self, tag: u32, val: u64) -> io::Result<()> {
+            self.events.push(format!("fixed64 {} {}", tag, val));
+            Ok(())
+        }
+
+        fn visit_bytes(&mut self, tag: u32, val: &[u8]) -> io::Result<()> {
+            self.events.push(format!("bytes {} {:?}", tag, val));
+            Ok(())
+        }
+
+        fn visit_group(&mut self, tag: u32, val: &[u8]) -> io::Result<()> {
+            self.events.push(format!("group {} {:?}", tag, val));
+            Ok(())
+        }
+
+        fn enter_message(&mut self, tag: u32, _: &[u8]) -> io::Result<bool> {
+            if tag == 4 {
+                self.events.push(format!("enter {}", tag));
+                return Ok(true);
+            }
+
+            Ok(false)
+        }
+
+        fn exit_message(&mut self, tag: u32) -> io::Result<()> {
+            self.events.push(format!("exit {}", tag));
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_visiting_fields() {
+        let bytes = b"\x08\x96\x01\x15\x01\x00\x00\x00\x19\x02\x00\x00\x00\x00\x00\x00\x00\x1A\x02hi\x22\x04\x08\x01\x22\x00\x28\x03";
+        let mut recorder = Recorder::default();
+
+        visit(&bytes[..], &mut recorder).unwrap();
+
+        assert_eq!(recorder.events, [
+            "varint 1 150",
+            "fixed32 2 1",
+            "fixed64 3 2",
+            "bytes 3 [104, 105]",
+            "enter 4",
+            "varint 1 1",
+            "enter 4",
+            "exit 4",
+            "exit 4",
+            "varint 5 3",
+        ]);
+    }
+
+    #[test]
+    pub fn test_visiting_deeply_nested_messages() {
+        struct Depth {
+            curr: usize,
+            max: usize,
+        }
+
+        impl Visitor for Depth {
+            fn enter_message(&mut self, _: u32, _: &[u8]) -> io::Result<bool> {
+                self.curr += 1;
+                self.max = cmp::max(self.max, self.curr);
+                Ok(true)
+            }
+
+            fn exit_message(&mut self, _: u32) -> io::Result<()> {
+                self.curr -= 1;
+                Ok(())
+            }
+        }
+
+        const DEPTH: usize = 100_000;
+
+        // The size of each message, from the innermost one out
+        let mut sizes = vec![0usize];
+
+        for i in 0..DEPTH {
+            let size = sizes[i];
+            sizes.push(1 + size.wire_len() + size);
+        }
+
+        let mut bytes = vec![];
+
+        for &size in sizes[..DEPTH].iter().rev() {
+            bytes.push(0x0A);
+            size.write(&mut bytes).unwrap();
+        }
+
+        let mut depth = Depth { curr: 0, max: 0 };
+        visit(&bytes[..], &mut depth).unwrap();
+
+        assert_eq!(depth.max, DEPTH);
+        assert_eq!(depth.curr, 0);
+    }
+
+    #[test]
+    pub fn test_visiting_groups() {
+        let mut recorder = Recorder::default();
+
+        visit(&b"\x0B\x08\x01\x0C\x10\x02"[..], &mut recorder).unwrap();
+        assert_eq!(recorder.events, ["group 1 [8, 1]", "varint 2 2"]);
+
+        assert!(visit(&b"\x0C"[..], &mut recorder).is_err());
+    }
+
+    #[test]
+    pub fn test_visiting_truncated_message() {
+        let mut recorder = Recorder::default();
+
+        assert!(visit(&b"\x08\x01\x22\x05\x08"[..], &mut recorder).is_err());
+        assert_eq!(recorder.events, ["varint 1 1"]);
+    }
+
+    #[test]
+    pub fn test_stopping_on_visitor_error() {
+        struct Fail;
+
+        impl Visitor for Fail {
+            fn visit_varint(&mut self, _: u32, _: u64) -> io::Result<()> {
+                Err(io::Error::new(io::ErrorKind::Interrupted, "stop"))
+            }
+        }
+
+        let err = visit(&b"\x08\x01"[..], &mut Fail).unwrap_err();
+        assert_eq!(err.to_string(), "stop");
+    }
+}
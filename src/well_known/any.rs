@@ -0,0 +1,220 @@
+use {deserialize, serialize, Deserialize, ErrorKind, InputStream, Merge, OutputStream, Serialize};
+use super::TypeName;
+use std::any::Any as StdAny;
+use std::collections::HashMap;
+use std::io;
+
+/// The type URL prefix used by `Any::pack`.
+const DEFAULT_URL_PREFIX: &str = "type.googleapis.com";
+
+/*
+ *
+ * ===== Any =====
+ *
+ */
+
+/// `google.protobuf.Any`, a serialized message along with a URL identifying
+/// its type.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Any {
+    pub type_url: String,
+    pub value: Vec<u8>,
+}
+
+impl Any {
+    /// Serializes `msg` into an `Any` with a `type.googleapis.com/` type URL.
+    pub fn pack<T: Serialize + TypeName>(msg: &T) -> io::Result<Any> {
+        Any::pack_with_prefix(msg, DEFAULT_URL_PREFIX)
+    }
+
+    /// Serializes `msg` into an `Any` with a type URL starting with `prefix`.
+    pub fn pack_with_prefix<T: Serialize + TypeName>(msg: &T, prefix: &str) -> io::Result<Any> {
+        Ok(Any {
+            type_url: format!("{}/{}", prefix.trim_end_matches('/'), T::type_name()),
+            value: try!(serialize(msg)),
+        })
+    }
+
+    /// Returns the fully qualified name of the packed message type, which is
+    /// the part of the type URL following the last `/`.
+    pub fn type_name(&self) -> &str {
+        match self.type_url.rfind('/') {
+            Some(pos) => &self.type_url[pos + 1..],
+            None => &self.type_url,
+        }
+    }
+
+    /// Returns `true` if the packed message is a `T`.
+    pub fn is<T: TypeName>(&self) -> bool {
+        self.type_name() == T::type_name()
+    }
+
+    /// Deserializes the packed message, returning `Ok(None)` if it is not a
+    /// `T`.
+    pub fn unpack<T: Deserialize + TypeName>(&self) -> io::Result<Option<T>> {
+        if !self.is::<T>() {
+            return Ok(None);
+        }
+
+        deserialize(&self.value[..]).map(Some)
+    }
+}
+
+impl TypeName for Any {
+    fn type_name() -> &'static str {
+        "google.protobuf.Any"
+    }
+}
+
+impl Serialize for Any {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        if !self.type_url.is_empty() {
+            try!(out.write(1, &self.type_url));
+        }
+
+        if !self.value.is_empty() {
+            try!(out.write(2, &self.value));
+        }
+
+        Ok(())
+    }
+}
+
+impl Deserialize for Any {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Any> {
+        let mut any = Any::default();
+        try!(any.merge_from(i));
+        Ok(any)
+    }
+}
+
+impl Merge for Any {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => self.type_url = try!(f.read()),
+                2 => self.value = try!(f.read()),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/*
+ *
+ * ===== TypeRegistry =====
+ *
+ */
+
+type Unpack = fn(&[u8]) -> io::Result<Box<dyn StdAny>>;
+
+/// Maps type URLs to message types, allowing `Any` values to be unpacked
+/// without knowing their type ahead of time.
+///
+/// Types are registered under their type name, so type URLs match regardless
+/// of their prefix.
+#[derive(Default)]
+pub struct TypeRegistry {
+    types: HashMap<&'static str, Unpack>,
+}
+
+impl TypeRegistry {
+    pub fn new() -> TypeRegistry {
+        TypeRegistry { types: HashMap::new() }
+    }
+
+    /// Registers `T` so that it can be unpacked dynamically.
+    pub fn register<T: Deserialize + TypeName + 'static>(&mut self) {
+        self.types.insert(T::type_name(), unpack::<T>);
+    }
+
+    /// Returns `true` if a type was registered for `type_url`.
+    pub fn contains(&self, type_url: &str) -> bool {
+        let name = match type_url.rfind('/') {
+            Some(pos) => &type_url[pos + 1..],
+            None => type_url,
+        };
+
+        self.types.contains_key(name)
+    }
+
+    /// Deserializes the message packed in `any`. The returned value can be
+    /// downcast to the registered type.
+    ///
+    /// Returns an `ErrorKind::UnknownType` error if the type was not
+    /// registered.
+    pub fn unpack(&self, any: &Any) -> io::Result<Box<dyn StdAny>> {
+        match self.types.get(any.type_name()) {
+            Some(unpack) => unpack(&any.value),
+            None => Err(ErrorKind::UnknownType.into()),
+        }
+    }
+}
+
+fn unpack<T: Deserialize + 'static>(bytes: &[u8]) -> io::Result<Box<dyn StdAny>> {
+    let msg: T = try!(deserialize(bytes));
+    Ok(Box::new(msg))
+}
+
+#[cfg(test)]
+mod test {
+    use {serialize, deserialize, Error, ErrorKind};
+    use super::{Any, TypeRegistry};
+    use well_known::{Duration, StringValue, Timestamp};
+
+    #[test]
+    pub fn test_packing_and_unpacking() {
+        let duration = Duration { seconds: 3, nanos: 0 };
+        let any = Any::pack(&duration).unwrap();
+
+        assert_eq!(any.type_url, "type.googleapis.com/google.protobuf.Duration");
+        assert_eq!(any.value, b"\x08\x03");
+        assert!(any.is::<Duration>());
+
+        assert_eq!(any.unpack::<Duration>().unwrap(), Some(duration));
+        assert_eq!(any.unpack::<Timestamp>().unwrap(), None);
+    }
+
+    #[test]
+    pub fn test_encoding_any() {
+        let any = Any::pack_with_prefix(&StringValue::from("a".to_string()), "example.com/types/").unwrap();
+        assert_eq!(any.type_url, "example.com/types/google.protobuf.StringValue");
+
+        let bytes = serialize(&any).unwrap();
+        let mut expect = b"\x0A\x2Dexample.com/types/google.protobuf.StringValue".to_vec();
+        expect.extend_from_slice(b"\x12\x03\x0A\x01a");
+
+        assert_eq!(bytes, expect);
+        assert_eq!(deserialize::<Any, _>(&bytes[..]).unwrap(), any);
+    }
+
+    #[test]
+    pub fn test_unpacking_dynamically() {
+        let mut registry = TypeRegistry::new();
+        registry.register::<Duration>();
+        registry.register::<StringValue>();
+
+        let anys = [
+            Any::pack(&Duration { seconds: 1, nanos: 2 }).unwrap(),
+            Any::pack(&StringValue::from("hello".to_string())).unwrap(),
+        ];
+
+        let msgs: Vec<_> = anys.iter().map(|any| registry.unpack(any).unwrap()).collect();
+
+        assert_eq!(msgs[0].downcast_ref::<Duration>(), Some(&Duration { seconds: 1, nanos: 2 }));
+        assert_eq!(msgs[1].downcast_ref::<StringValue>().unwrap().value, "hello");
+    }
+
+    #[test]
+    pub fn test_unpacking_unregistered_type() {
+        let registry = TypeRegistry::new();
+        let any = Any::pack(&Timestamp::default()).unwrap();
+
+        assert!(!registry.contains(&any.type_url));
+
+        let err = registry.unpack(&any).unwrap_err();
+        assert_eq!(Error::from_io(&err).unwrap().kind(), ErrorKind::UnknownType);
+    }
+}
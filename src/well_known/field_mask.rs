@@ -0,0 +1,252 @@
+use {deserialize, serialize, Deserialize, Descriptor, Error, ErrorKind, InputStream, Merge,
+     MessageDescriptor, OutputStream, Serialize, Varint, WireType};
+use super::TypeName;
+use std::collections::BTreeMap;
+use std::io;
+
+/// `google.protobuf.FieldMask`, a set of field paths such as `address.city`
+/// selecting a subset of the fields of a message.
+///
+/// Paths are made of field names separated by `.`. Every field named in a
+/// path, except the last one, must be a singular message field.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FieldMask {
+    pub paths: Vec<String>,
+}
+
+/// The fields selected by a mask, keyed by field number. `None` selects the
+/// whole field.
+type Selection = BTreeMap<u32, Option<Node>>;
+
+#[derive(Debug)]
+struct Node {
+    fields: Selection,
+}
+
+impl FieldMask {
+    /// Returns a new mask holding `paths`.
+    pub fn new<I, S>(paths: I) -> FieldMask
+            where I: IntoIterator<Item=S>,
+                  S: Into<String> {
+        FieldMask { paths: paths.into_iter().map(Into::into).collect() }
+    }
+
+    /// Parses the JSON representation of a mask, a comma separated list of
+    /// paths such as `"name,address.city"`.
+    ///
+    /// Paths are checked to be made of valid field names, but not against a
+    /// message type, see `FieldMask::validate`.
+    pub fn parse(s: &str) -> Result<FieldMask, Error> {
+        if s.is_empty() {
+            return Ok(FieldMask::default());
+        }
+
+        let mask = FieldMask::new(s.split(','));
+
+        for path in &mask.paths {
+            if !path.split('.').all(is_field_name) {
+                return Err(invalid_path());
+            }
+        }
+
+        Ok(mask)
+    }
+
+    /// Checks that every path names a field of `T`.
+    pub fn validate<T: Descriptor>(&self) -> Result<(), Error> {
+        self.selection(T::descriptor()).map(|_| ())
+    }
+
+    /// Serializes the fields of `msg` selected by the mask.
+    ///
+    /// A nested message on a path is written, with only its selected fields,
+    /// whenever it is present in `msg`.
+    pub fn serialize_masked<T>(&self, msg: &T) -> io::Result<Vec<u8>>
+            where T: Serialize + Descriptor {
+        let selection = try!(self.selection(T::descriptor()));
+        let bytes = try!(serialize(msg));
+
+        let mut ret = Vec::with_capacity(bytes.len());
+        try!(filter(&bytes, &selection, true, &mut ret));
+
+        Ok(ret)
+    }
+
+    /// Replaces the fields of `dst` selected by the mask with those of `src`.
+    ///
+    /// Selected fields that `src` does not serialize are cleared in `dst` and
+    /// selected repeated fields are replaced rather than appended to. Nested
+    /// messages on a path keep their fields that are not selected.
+    ///
+    /// `dst` is rebuilt from its encoding, dropping anything that `T` does
+    /// not serialize.
+    pub fn merge_masked<T>(&self, src: &T, dst: &mut T) -> io::Result<()>
+            where T: Serialize + Merge + Descriptor {
+        let selection = try!(self.selection(T::descriptor()));
+
+        // The unselected fields of `dst` followed by the selected fields of
+        // `src`, nested messages on a path being merged when decoding
+        let mut bytes = Vec::new();
+        try!(filter(&try!(serialize(dst)), &selection, false, &mut bytes));
+        try!(filter(&try!(serialize(src)), &selection, true, &mut bytes));
+
+        *dst = try!(deserialize(&bytes[..]));
+        Ok(())
+    }
+
+    /// Resolves the paths against `descriptor`.
+    fn selection(&self, descriptor: &'static MessageDescriptor) -> Result<Selection, Error> {
+        let mut ret = Selection::new();
+
+        for path in &self.paths {
+            let mut fields = &mut ret;
+            let mut descriptor = Some(descriptor);
+            let mut segments = path.split('.').peekable();
+
+            while let Some(segment) = segments.next() {
+                let field = match descriptor.and_then(|d| d.field_by_name(segment)) {
+                    Some(field) => field,
+                    None => return Err(invalid_path()),
+                };
+
+                if segments.peek().is_none() {
+                    // The whole field is selected, which covers any sub-path
+                    fields.insert(field.number, None);
+                    break;
+                }
+
+                if field.repeated {
+                    return Err(invalid_path());
+                }
+
+                descriptor = field.message_descriptor();
+
+                let node = fields.entry(field.number)
+                    .or_insert_with(|| Some(Node { fields: Selection::new() }));
+
+                match *node {
+                    Some(ref mut node) => fields = &mut node.fields,
+                    // A parent path already selects the whole field
+                    None => break,
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
+impl TypeName for FieldMask {
+    fn type_name() -> &'static str {
+        "google.protobuf.FieldMask"
+    }
+}
+
+impl Serialize for FieldMask {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        out.write_repeated(1, &self.paths)
+    }
+}
+
+impl Deserialize for FieldMask {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<FieldMask> {
+        let mut ret = FieldMask::default();
+        try!(ret.merge_from(i));
+        Ok(ret)
+    }
+}
+
+impl Merge for FieldMask {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => self.paths.push(try!(f.read())),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Copies the fields of the encoded message `bytes` selected by `selection`
+/// to `dst`, or the fields not selected if `selected` is `false`. Nested
+/// messages on a path are filtered recursively.
+fn filter(bytes: &[u8], selection: &Selection, selected: bool, dst: &mut Vec<u8>) -> io::Result<()> {
+    let mut input = InputStream::new(bytes);
+
+    loop {
+        let start = input.position() as usize;
+
+        let f = match try!(input.read_field()) {
+            Some(f) => f,
+            None => return Ok(()),
+        };
+
+        let copy = match selection.get(&f.tag()) {
+            Some(None) => selected,
+            Some(Some(node)) if f.wire_type() == WireType::LengthDelimited => {
+                let tag = f.tag();
+                let nested: Vec<u8> = try!(f.read());
+
+                let mut filtered = Vec::new();
+                try!(filter(&nested, &node.fields, selected, &mut filtered));
+
+                try!(((tag << 3) | WireType::LengthDelimited as u32).write(dst));
+                try!(filtered.len().write(dst));
+                dst.extend_from_slice(&filtered);
+
+                continue;
+            }
+            _ => !selected,
+        };
+
+        try!(f.skip());
+
+        if copy {
+            dst.extend_from_slice(&bytes[start..input.position() as usize]);
+        }
+    }
+}
+
+fn is_field_name(s: &str) -> bool {
+    let mut chars = s.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn invalid_path() -> Error {
+    Error::new(ErrorKind::InvalidValue("invalid field mask path"))
+}
+
+#[cfg(test)]
+mod test {
+    use {serialize, deserialize};
+    use super::FieldMask;
+
+    #[test]
+    pub fn test_parsing_field_mask() {
+        let mask = FieldMask::parse("name,address.city").unwrap();
+        assert_eq!(mask, FieldMask::new(vec!["name", "address.city"]));
+
+        assert_eq!(FieldMask::parse("").unwrap(), FieldMask::default());
+
+        for s in ["a,", "a..b", ".a", "1a", "a-b", "a.b c"].iter() {
+            assert!(FieldMask::parse(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    pub fn test_encoding_field_mask() {
+        let mask = FieldMask::new(vec!["a", "b.c"]);
+        let bytes = serialize(&mask).unwrap();
+
+        assert_eq!(bytes, b"\x0A\x01a\x0A\x03b.c");
+        assert_eq!(deserialize::<FieldMask, _>(&bytes[..]).unwrap(), mask);
+    }
+}
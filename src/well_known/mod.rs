@@ -0,0 +1,551 @@
+//! Well-known types from the `google.protobuf` package.
+
+use {Deserialize, Error, ErrorKind, InputStream, Merge, OutputStream, Serialize};
+use std::convert::TryFrom;
+use std::io;
+use std::time::{self, SystemTime, UNIX_EPOCH};
+
+pub use self::any::{Any, TypeRegistry};
+pub use self::field_mask::FieldMask;
+pub use self::structs::{ListValue, Struct, Value};
+
+mod any;
+mod field_mask;
+mod structs;
+
+const NANOS_PER_SECOND: i32 = 1_000_000_000;
+
+/// Messages with a fully qualified Protocol Buffers type name, such as
+/// `google.protobuf.Duration`.
+pub trait TypeName {
+    /// Returns the fully qualified type name, without a leading dot.
+    fn type_name() -> &'static str;
+}
+
+/*
+ *
+ * ===== Timestamp =====
+ *
+ */
+
+/// `google.protobuf.Timestamp`, a point in time independent of any time zone.
+///
+/// Valid timestamps range from `0001-01-01T00:00:00Z` to
+/// `9999-12-31T23:59:59.999999999Z`, with `nanos` between 0 and 999,999,999
+/// inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Timestamp {
+    pub seconds: i64,
+    pub nanos: i32,
+}
+
+const TIMESTAMP_MIN_SECONDS: i64 = -62_135_596_800;
+const TIMESTAMP_MAX_SECONDS: i64 = 253_402_300_799;
+
+impl Timestamp {
+    /// Returns an error if the timestamp is outside of the valid range.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.seconds < TIMESTAMP_MIN_SECONDS || self.seconds > TIMESTAMP_MAX_SECONDS {
+            return Err(invalid("Timestamp seconds out of range"));
+        }
+
+        if self.nanos < 0 || self.nanos >= NANOS_PER_SECOND {
+            return Err(invalid("Timestamp nanos out of range"));
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<SystemTime> for Timestamp {
+    type Error = Error;
+
+    fn try_from(time: SystemTime) -> Result<Timestamp, Error> {
+        let ts = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => {
+                Timestamp {
+                    seconds: try!(secs_to_i64(since.as_secs())),
+                    nanos: since.subsec_nanos() as i32,
+                }
+            }
+            Err(e) => {
+                // `nanos` always counts forward, so round the seconds down
+                let before = e.duration();
+                let seconds = -try!(secs_to_i64(before.as_secs()));
+
+                match before.subsec_nanos() as i32 {
+                    0 => Timestamp { seconds: seconds, nanos: 0 },
+                    nanos => Timestamp { seconds: seconds - 1, nanos: NANOS_PER_SECOND - nanos },
+                }
+            }
+        };
+
+        try!(ts.validate());
+        Ok(ts)
+    }
+}
+
+impl TryFrom<Timestamp> for SystemTime {
+    type Error = Error;
+
+    fn try_from(ts: Timestamp) -> Result<SystemTime, Error> {
+        try!(ts.validate());
+
+        let nanos = time::Duration::from_nanos(ts.nanos as u64);
+
+        let time = if ts.seconds >= 0 {
+            UNIX_EPOCH.checked_add(time::Duration::from_secs(ts.seconds as u64))
+        } else {
+            UNIX_EPOCH.checked_sub(time::Duration::from_secs(-ts.seconds as u64))
+        };
+
+        time.and_then(|t| t.checked_add(nanos))
+            .ok_or_else(|| invalid("Timestamp not representable as SystemTime"))
+    }
+}
+
+/*
+ *
+ * ===== Duration =====
+ *
+ */
+
+/// `google.protobuf.Duration`, a signed span of time.
+///
+/// Valid durations are at most 315,576,000,000 seconds (about 10,000 years)
+/// in either direction. `nanos` is between -999,999,999 and 999,999,999
+/// inclusive and must have the same sign as `seconds` when both are non-zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Duration {
+    pub seconds: i64,
+    pub nanos: i32,
+}
+
+const DURATION_MAX_SECONDS: i64 = 315_576_000_000;
+
+impl Duration {
+    /// Returns an error if the duration is outside of the valid range.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.seconds < -DURATION_MAX_SECONDS || self.seconds > DURATION_MAX_SECONDS {
+            return Err(invalid("Duration seconds out of range"));
+        }
+
+        if self.nanos <= -NANOS_PER_SECOND || self.nanos >= NANOS_PER_SECOND {
+            return Err(invalid("Duration nanos out of range"));
+        }
+
+        if (self.seconds > 0 && self.nanos < 0) || (self.seconds < 0 && self.nanos > 0) {
+            return Err(invalid("Duration seconds and nanos have different signs"));
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<time::Duration> for Duration {
+    type Error = Error;
+
+    fn try_from(duration: time::Duration) -> Result<Duration, Error> {
+        let duration = Duration {
+            seconds: try!(secs_to_i64(duration.as_secs())),
+            nanos: duration.subsec_nanos() as i32,
+        };
+
+        try!(duration.validate());
+        Ok(duration)
+    }
+}
+
+impl TryFrom<Duration> for time::Duration {
+    type Error = Error;
+
+    fn try_from(duration: Duration) -> Result<time::Duration, Error> {
+        try!(duration.validate());
+
+        if duration.seconds < 0 || duration.nanos < 0 {
+            return Err(invalid("negative Duration not representable as std::time::Duration"));
+        }
+
+        Ok(time::Duration::new(duration.seconds as u64, duration.nanos as u32))
+    }
+}
+
+/*
+ *
+ * ===== Timestamp & Duration encoding =====
+ *
+ */
+
+macro_rules! impl_seconds_nanos {
+    ($Ty:ident) => {
+        impl TypeName for $Ty {
+            fn type_name() -> &'static str {
+                concat!("google.protobuf.", stringify!($Ty))
+            }
+        }
+
+        impl Serialize for $Ty {
+            fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+                // Zero values are omitted, as protoc does for proto3 scalars
+                if self.seconds != 0 {
+                    try!(out.write(1, &self.seconds));
+                }
+
+                if self.nanos != 0 {
+                    try!(out.write(2, &self.nanos));
+                }
+
+                Ok(())
+            }
+        }
+
+        impl Deserialize for $Ty {
+            fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<$Ty> {
+                let mut ret = $Ty::default();
+                try!(ret.merge_from(i));
+                Ok(ret)
+            }
+        }
+
+        impl Merge for $Ty {
+            fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+                while let Some(f) = try!(i.read_field()) {
+                    match f.tag() {
+                        1 => self.seconds = try!(f.read()),
+                        2 => self.nanos = try!(f.read()),
+                        _ => try!(f.skip()),
+                    }
+                }
+
+                try!(self.validate());
+                Ok(())
+            }
+        }
+    }
+}
+
+impl_seconds_nanos! { Timestamp }
+impl_seconds_nanos! { Duration }
+
+/*
+ *
+ * ===== Empty =====
+ *
+ */
+
+/// `google.protobuf.Empty`, a message with no fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Empty;
+
+impl TypeName for Empty {
+    fn type_name() -> &'static str {
+        "google.protobuf.Empty"
+    }
+}
+
+impl Serialize for Empty {
+    fn serialize<O: OutputStream>(&self, _: &mut O) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Deserialize for Empty {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Empty> {
+        try!(Empty.merge_from(i));
+        Ok(Empty)
+    }
+}
+
+impl Merge for Empty {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            try!(f.skip());
+        }
+
+        Ok(())
+    }
+}
+
+/*
+ *
+ * ===== Wrappers =====
+ *
+ */
+
+/// Scalar types that have a `google.protobuf.*Value` wrapper message.
+///
+/// Wrappers give a scalar explicit presence. `OutputStream::write_wrapped`
+/// and `Field::read_wrapped` use them to encode an `Option<T>` as the wrapper
+/// message rather than as a plain scalar field.
+pub trait Wrap: Serialize + Deserialize {
+    /// The wrapper message type
+    type Wrapper: Serialize + Deserialize + From<Self> + Into<Self>;
+}
+
+/// Encodes a borrowed scalar as its wrapper message. As with protoc, the
+/// value is omitted when it is the default.
+pub(crate) struct Wrapped<'a, T: ?Sized + 'a>(pub &'a T);
+
+impl<'a, T: ?Sized + Serialize> Serialize for Wrapped<'a, T> {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        if self.0.is_default() {
+            return Ok(());
+        }
+
+        out.write(1, self.0)
+    }
+}
+
+macro_rules! wrapper {
+    ($(#[$attr:meta])* $Name:ident, $Ty:ty) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, PartialEq, Default)]
+        pub struct $Name {
+            pub value: $Ty,
+        }
+
+        impl TypeName for $Name {
+            fn type_name() -> &'static str {
+                concat!("google.protobuf.", stringify!($Name))
+            }
+        }
+
+        impl Serialize for $Name {
+            fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+                Wrapped(&self.value).serialize(out)
+            }
+        }
+
+        impl Deserialize for $Name {
+            fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<$Name> {
+                let mut ret = $Name::default();
+                try!(ret.merge_from(i));
+                Ok(ret)
+            }
+        }
+
+        impl Merge for $Name {
+            fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+                while let Some(f) = try!(i.read_field()) {
+                    match f.tag() {
+                        1 => self.value = try!(f.read()),
+                        _ => try!(f.skip()),
+                    }
+                }
+
+                Ok(())
+            }
+        }
+
+        impl From<$Ty> for $Name {
+            fn from(value: $Ty) -> $Name {
+                $Name { value: value }
+            }
+        }
+
+        impl From<$Name> for $Ty {
+            fn from(wrapper: $Name) -> $Ty {
+                wrapper.value
+            }
+        }
+
+        impl Wrap for $Ty {
+            type Wrapper = $Name;
+        }
+    }
+}
+
+wrapper! {
+    /// `google.protobuf.DoubleValue`, a wrapper for `double`.
+    DoubleValue, f64
+}
+
+wrapper! {
+    /// `google.protobuf.FloatValue`, a wrapper for `float`.
+    FloatValue, f32
+}
+
+wrapper! {
+    /// `google.protobuf.Int64Value`, a wrapper for `int64`.
+    Int64Value, i64
+}
+
+wrapper! {
+    /// `google.protobuf.UInt64Value`, a wrapper for `uint64`.
+    UInt64Value, u64
+}
+
+wrapper! {
+    /// `google.protobuf.Int32Value`, a wrapper for `int32`.
+    Int32Value, i32
+}
+
+wrapper! {
+    /// `google.protobuf.UInt32Value`, a wrapper for `uint32`.
+    UInt32Value, u32
+}
+
+wrapper! {
+    /// `google.protobuf.BoolValue`, a wrapper for `bool`.
+    BoolValue, bool
+}
+
+wrapper! {
+    /// `google.protobuf.StringValue`, a wrapper for `string`.
+    StringValue, String
+}
+
+wrapper! {
+    /// `google.protobuf.BytesValue`, a wrapper for `bytes`.
+    BytesValue, Vec<u8>
+}
+
+/*
+ *
+ * ===== Misc =====
+ *
+ */
+
+fn secs_to_i64(secs: u64) -> Result<i64, Error> {
+    if secs > i64::MAX as u64 {
+        return Err(invalid("seconds out of range"));
+    }
+
+    Ok(secs as i64)
+}
+
+fn invalid(desc: &'static str) -> Error {
+    Error::new(ErrorKind::InvalidValue(desc))
+}
+
+#[cfg(test)]
+mod test {
+    use {serialize, deserialize, ErrorKind, InputStream, OutputStream, Serialize, Deserialize};
+    use super::*;
+    use std::convert::TryFrom;
+    use std::io;
+    use std::time::{self, SystemTime, UNIX_EPOCH};
+
+    #[test]
+    pub fn test_encoding_timestamp() {
+        let ts = Timestamp { seconds: 1_500_000_000, nanos: 5 };
+        let bytes = serialize(&ts).unwrap();
+
+        assert_eq!(bytes, b"\x08\x80\xde\xa0\xcb\x05\x10\x05");
+        assert_eq!(deserialize::<Timestamp, _>(&bytes[..]).unwrap(), ts);
+
+        assert!(serialize(&Timestamp::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn test_converting_timestamp() {
+        let time = UNIX_EPOCH + time::Duration::new(1_500_000_000, 123);
+        let ts = Timestamp::try_from(time).unwrap();
+        assert_eq!(ts, Timestamp { seconds: 1_500_000_000, nanos: 123 });
+        assert_eq!(SystemTime::try_from(ts).unwrap(), time);
+
+        let time = UNIX_EPOCH - time::Duration::new(1, 250_000_000);
+        let ts = Timestamp::try_from(time).unwrap();
+        assert_eq!(ts, Timestamp { seconds: -2, nanos: 750_000_000 });
+        assert_eq!(SystemTime::try_from(ts).unwrap(), time);
+    }
+
+    #[test]
+    pub fn test_validating_timestamp() {
+        assert!(Timestamp { seconds: 253_402_300_799, nanos: 999_999_999 }.validate().is_ok());
+        assert!(Timestamp { seconds: -62_135_596_800, nanos: 0 }.validate().is_ok());
+        assert!(Timestamp { seconds: 253_402_300_800, nanos: 0 }.validate().is_err());
+        assert!(Timestamp { seconds: 0, nanos: -1 }.validate().is_err());
+
+        let bytes = serialize(&Timestamp { seconds: 0, nanos: 1_000_000_000 }).unwrap();
+        let err = deserialize::<Timestamp, _>(&bytes[..]).unwrap_err();
+        let err = Error::from_io(&err).unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidValue("Timestamp nanos out of range"));
+    }
+
+    #[test]
+    pub fn test_converting_duration() {
+        let std = time::Duration::new(90, 500);
+        let duration = Duration::try_from(std).unwrap();
+        assert_eq!(duration, Duration { seconds: 90, nanos: 500 });
+        assert_eq!(time::Duration::try_from(duration).unwrap(), std);
+
+        assert!(time::Duration::try_from(Duration { seconds: -1, nanos: 0 }).is_err());
+        assert!(Duration::try_from(time::Duration::from_secs(315_576_000_001)).is_err());
+    }
+
+    #[test]
+    pub fn test_validating_duration() {
+        assert!(Duration { seconds: -315_576_000_000, nanos: -999_999_999 }.validate().is_ok());
+        assert!(Duration { seconds: 0, nanos: -5 }.validate().is_ok());
+        assert!(Duration { seconds: 1, nanos: -5 }.validate().is_err());
+        assert!(Duration { seconds: 315_576_000_001, nanos: 0 }.validate().is_err());
+    }
+
+    #[test]
+    pub fn test_encoding_negative_duration() {
+        let duration = Duration { seconds: -1, nanos: -2 };
+        let bytes = serialize(&duration).unwrap();
+
+        assert_eq!(bytes, &b"\x08\xff\xff\xff\xff\xff\xff\xff\xff\xff\x01\x10\xfe\xff\xff\xff\xff\xff\xff\xff\xff\x01"[..]);
+        assert_eq!(deserialize::<Duration, _>(&bytes[..]).unwrap(), duration);
+    }
+
+    #[test]
+    pub fn test_encoding_wrappers() {
+        assert_eq!(serialize(&UInt32Value { value: 150 }).unwrap(), b"\x08\x96\x01");
+        assert_eq!(serialize(&StringValue::from("hi".to_string())).unwrap(), b"\x0A\x02hi");
+        assert_eq!(serialize(&DoubleValue { value: 1.5 }).unwrap(), b"\x09\x00\x00\x00\x00\x00\x00\xf8\x3f");
+        assert!(serialize(&BoolValue { value: false }).unwrap().is_empty());
+
+        assert_eq!(deserialize::<FloatValue, _>(&b"\x0D\x00\x00\xc0\x3f"[..]).unwrap().value, 1.5);
+        assert_eq!(deserialize::<Int64Value, _>(&b""[..]).unwrap().value, 0);
+    }
+
+    #[test]
+    pub fn test_encoding_optional_scalar_as_wrapper() {
+        struct Msg {
+            count: Option<u32>,
+            name: Option<String>,
+        }
+
+        impl Serialize for Msg {
+            fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+                try!(out.write_wrapped(1, &self.count));
+                try!(out.write_wrapped(2, &self.name));
+                Ok(())
+            }
+        }
+
+        impl Deserialize for Msg {
+            fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Msg> {
+                let mut msg = Msg { count: None, name: None };
+
+                while let Some(f) = try!(i.read_field()) {
+                    match f.tag() {
+                        1 => msg.count = Some(try!(f.read_wrapped())),
+                        2 => msg.name = Some(try!(f.read_wrapped())),
+                        _ => try!(f.skip()),
+                    }
+                }
+
+                Ok(msg)
+            }
+        }
+
+        // Some(0) keeps its presence as an empty wrapper message
+        let bytes = serialize(&Msg { count: Some(0), name: None }).unwrap();
+        assert_eq!(bytes, b"\x0A\x00");
+
+        let msg: Msg = deserialize(&bytes[..]).unwrap();
+        assert_eq!(msg.count, Some(0));
+        assert_eq!(msg.name, None);
+
+        let bytes = serialize(&Msg { count: Some(3), name: Some("a".to_string()) }).unwrap();
+        assert_eq!(bytes, b"\x0A\x02\x08\x03\x12\x03\x0A\x01a");
+
+        let msg: Msg = deserialize(&bytes[..]).unwrap();
+        assert_eq!(msg.count, Some(3));
+        assert_eq!(msg.name, Some("a".to_string()));
+    }
+}
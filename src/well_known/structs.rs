@@ -0,0 +1,487 @@
+use {Deserialize, InputStream, Merge, OutputStream, Serialize};
+use super::TypeName;
+use std::collections::BTreeMap;
+use std::io;
+
+/*
+ *
+ * ===== Struct =====
+ *
+ */
+
+/// `google.protobuf.Struct`, a map of field names to dynamically typed
+/// values, representing a JSON object.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Struct {
+    pub fields: BTreeMap<String, Value>,
+}
+
+impl TypeName for Struct {
+    fn type_name() -> &'static str {
+        "google.protobuf.Struct"
+    }
+}
+
+impl Serialize for Struct {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        out.write(1, &self.fields)
+    }
+}
+
+impl Deserialize for Struct {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Struct> {
+        let mut ret = Struct::default();
+        try!(ret.merge_from(i));
+        Ok(ret)
+    }
+}
+
+impl Merge for Struct {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => try!(f.merge_into(&mut self.fields)),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/*
+ *
+ * ===== Value =====
+ *
+ */
+
+/// `google.protobuf.Value`, a dynamically typed value representing any JSON
+/// value.
+///
+/// A message with none of the `kind` fields set decodes as `Value::Null`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Value {
+    #[default]
+    Null,
+    Number(f64),
+    String(String),
+    Bool(bool),
+    Struct(Struct),
+    List(ListValue),
+}
+
+impl TypeName for Value {
+    fn type_name() -> &'static str {
+        "google.protobuf.Value"
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        // The fields form a oneof, so the set field keeps its presence and is
+        // written even when it holds the default value, including in proto3
+        match *self {
+            // `NullValue.NULL_VALUE`
+            Value::Null => out.write(1, &Some(0u32)),
+            Value::Number(ref val) => out.write(2, &Some(val)),
+            Value::String(ref val) => out.write(3, &Some(val)),
+            Value::Bool(ref val) => out.write(4, &Some(val)),
+            Value::Struct(ref val) => out.write(5, val),
+            Value::List(ref val) => out.write(6, val),
+        }
+    }
+}
+
+impl Deserialize for Value {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Value> {
+        let mut ret = Value::default();
+        try!(ret.merge_from(i));
+        Ok(ret)
+    }
+}
+
+impl Merge for Value {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => {
+                    let _: u32 = try!(f.read());
+                    *self = Value::Null;
+                }
+                2 => *self = Value::Number(try!(f.read())),
+                3 => *self = Value::String(try!(f.read())),
+                4 => *self = Value::Bool(try!(f.read())),
+                5 => {
+                    // Repeated occurrences of a message field in a oneof merge
+                    if let Value::Struct(ref mut val) = *self {
+                        try!(f.merge_into(val));
+                        continue;
+                    }
+
+                    *self = Value::Struct(try!(f.read()));
+                }
+                6 => {
+                    if let Value::List(ref mut val) = *self {
+                        try!(f.merge_into(val));
+                        continue;
+                    }
+
+                    *self = Value::List(try!(f.read()));
+                }
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/*
+ *
+ * ===== ListValue =====
+ *
+ */
+
+/// `google.protobuf.ListValue`, a list of dynamically typed values,
+/// representing a JSON array.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ListValue {
+    pub values: Vec<Value>,
+}
+
+impl TypeName for ListValue {
+    fn type_name() -> &'static str {
+        "google.protobuf.ListValue"
+    }
+}
+
+impl Serialize for ListValue {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        out.write_repeated(1, &self.values)
+    }
+}
+
+impl Deserialize for ListValue {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<ListValue> {
+        let mut ret = ListValue::default();
+        try!(ret.merge_from(i));
+        Ok(ret)
+    }
+}
+
+impl Merge for ListValue {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => self.values.push(try!(f.read())),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/*
+ *
+ * ===== serde_json =====
+ *
+ * Conversions fail rather than lose information: JSON numbers that a double
+ * cannot represent exactly are rejected, as are NaN and infinite numbers,
+ * which JSON cannot represent. Integral numbers convert to JSON integers,
+ * except for negative zero which keeps its sign as a JSON float.
+ *
+ */
+
+#[cfg(feature = "serde_json")]
+mod json {
+    use {Error, ErrorKind};
+    use super::{ListValue, Struct, Value};
+    use serde_json::{self, Map, Number};
+    use std::convert::TryFrom;
+
+    /// The smallest doubles that no longer fit in an `i64` and a `u64`.
+    const I64_END: f64 = 9_223_372_036_854_775_808.0;
+    const U64_END: f64 = 18_446_744_073_709_551_616.0;
+
+    impl TryFrom<serde_json::Value> for Value {
+        type Error = Error;
+
+        fn try_from(json: serde_json::Value) -> Result<Value, Error> {
+            Ok(match json {
+                serde_json::Value::Null => Value::Null,
+                serde_json::Value::Bool(val) => Value::Bool(val),
+                serde_json::Value::Number(val) => Value::Number(try!(number_to_f64(&val))),
+                serde_json::Value::String(val) => Value::String(val),
+                serde_json::Value::Array(val) => Value::List(try!(ListValue::try_from(val))),
+                serde_json::Value::Object(val) => Value::Struct(try!(Struct::try_from(val))),
+            })
+        }
+    }
+
+    impl TryFrom<Value> for serde_json::Value {
+        type Error = Error;
+
+        fn try_from(value: Value) -> Result<serde_json::Value, Error> {
+            Ok(match value {
+                Value::Null => serde_json::Value::Null,
+                Value::Bool(val) => serde_json::Value::Bool(val),
+                Value::Number(val) => serde_json::Value::Number(try!(f64_to_number(val))),
+                Value::String(val) => serde_json::Value::String(val),
+                Value::List(val) => serde_json::Value::Array(try!(Vec::try_from(val))),
+                Value::Struct(val) => serde_json::Value::Object(try!(Map::try_from(val))),
+            })
+        }
+    }
+
+    impl TryFrom<Map<String, serde_json::Value>> for Struct {
+        type Error = Error;
+
+        fn try_from(json: Map<String, serde_json::Value>) -> Result<Struct, Error> {
+            let mut ret = Struct::default();
+
+            for (key, val) in json {
+                ret.fields.insert(key, try!(Value::try_from(val)));
+            }
+
+            Ok(ret)
+        }
+    }
+
+    impl TryFrom<Struct> for Map<String, serde_json::Value> {
+        type Error = Error;
+
+        fn try_from(value: Struct) -> Result<Map<String, serde_json::Value>, Error> {
+            let mut ret = Map::new();
+
+            for (key, val) in value.fields {
+                ret.insert(key, try!(serde_json::Value::try_from(val)));
+            }
+
+            Ok(ret)
+        }
+    }
+
+    impl TryFrom<Vec<serde_json::Value>> for ListValue {
+        type Error = Error;
+
+        fn try_from(json: Vec<serde_json::Value>) -> Result<ListValue, Error> {
+            let mut ret = ListValue::default();
+
+            for val in json {
+                ret.values.push(try!(Value::try_from(val)));
+            }
+
+            Ok(ret)
+        }
+    }
+
+    impl TryFrom<ListValue> for Vec<serde_json::Value> {
+        type Error = Error;
+
+        fn try_from(value: ListValue) -> Result<Vec<serde_json::Value>, Error> {
+            value.values.into_iter().map(serde_json::Value::try_from).collect()
+        }
+    }
+
+    fn number_to_f64(num: &Number) -> Result<f64, Error> {
+        // Casting back saturates, so integers rounded up past the end of
+        // their type's range are checked separately
+        if let Some(val) = num.as_i64() {
+            let double = val as f64;
+
+            if double >= I64_END || double as i64 != val {
+                return Err(inexact());
+            }
+
+            return Ok(double);
+        }
+
+        if let Some(val) = num.as_u64() {
+            let double = val as f64;
+
+            if double >= U64_END || double as u64 != val {
+                return Err(inexact());
+            }
+
+            return Ok(double);
+        }
+
+        num.as_f64().ok_or_else(inexact)
+    }
+
+    fn f64_to_number(val: f64) -> Result<Number, Error> {
+        if val.fract() == 0.0 && !(val == 0.0 && val.is_sign_negative()) {
+            if (-I64_END..I64_END).contains(&val) {
+                return Ok(Number::from(val as i64));
+            }
+
+            if (0.0..U64_END).contains(&val) {
+                return Ok(Number::from(val as u64));
+            }
+        }
+
+        Number::from_f64(val).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidValue("NaN or infinite number not representable as JSON"))
+        })
+    }
+
+    fn inexact() -> Error {
+        Error::new(ErrorKind::InvalidValue("JSON number not exactly representable as a double"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {serialize, serialize_proto3, deserialize};
+    use super::{ListValue, Struct, Value};
+
+    fn sample() -> Struct {
+        let mut nested = Struct::default();
+        nested.fields.insert("ok".to_string(), Value::Bool(false));
+
+        let mut ret = Struct::default();
+        ret.fields.insert("name".to_string(), Value::String("buffoon".to_string()));
+        ret.fields.insert("count".to_string(), Value::Number(3.0));
+        ret.fields.insert("none".to_string(), Value::Null);
+        ret.fields.insert("nested".to_string(), Value::Struct(nested));
+        ret.fields.insert("list".to_string(), Value::List(ListValue {
+            values: vec![Value::Number(1.5), Value::String("".to_string())],
+        }));
+
+        ret
+    }
+
+    #[test]
+    pub fn test_encoding_value() {
+        assert_eq!(serialize(&Value::Null).unwrap(), b"\x08\x00");
+        assert_eq!(serialize(&Value::Bool(false)).unwrap(), b"\x20\x00");
+        assert_eq!(serialize(&Value::Number(1.5)).unwrap(), b"\x11\x00\x00\x00\x00\x00\x00\xf8\x3f");
+        assert_eq!(serialize(&Value::String("a".to_string())).unwrap(), b"\x1A\x01a");
+
+        assert_eq!(deserialize::<Value, _>(&b""[..]).unwrap(), Value::Null);
+    }
+
+    #[test]
+    pub fn test_encoding_struct() {
+        let mut value = Struct::default();
+        value.fields.insert("a".to_string(), Value::Bool(true));
+
+        // fields entry { key: "a", value: { bool_value: true } }
+        let bytes = serialize(&value).unwrap();
+        assert_eq!(bytes, b"\x0A\x07\x0A\x01a\x12\x02\x20\x01");
+    }
+
+    #[test]
+    pub fn test_round_tripping_struct() {
+        let bytes = serialize(&sample()).unwrap();
+        assert_eq!(deserialize::<Struct, _>(&bytes[..]).unwrap(), sample());
+    }
+
+    #[test]
+    pub fn test_round_tripping_proto3_values() {
+        let values = [
+            Value::Null,
+            Value::Number(0.0),
+            Value::String("".to_string()),
+            Value::Bool(false),
+            Value::Struct(Struct::default()),
+            Value::List(ListValue::default()),
+        ];
+
+        for value in values.iter() {
+            let bytes = serialize_proto3(value).unwrap();
+            assert_eq!(bytes, serialize(value).unwrap());
+            assert_eq!(deserialize::<Value, _>(&bytes[..]).unwrap(), *value);
+        }
+
+        let bytes = serialize_proto3(&sample()).unwrap();
+        assert_eq!(deserialize::<Struct, _>(&bytes[..]).unwrap(), sample());
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    pub fn test_converting_json() {
+        use serde_json::{self, Map};
+        use std::convert::TryFrom;
+
+        let json = json_sample();
+        let value = Value::try_from(json.clone()).unwrap();
+        assert_eq!(value, Value::Struct(sample()));
+        assert_eq!(serde_json::Value::try_from(value).unwrap(), json);
+
+        let map = match json {
+            serde_json::Value::Object(map) => map,
+            _ => unreachable!(),
+        };
+
+        let value = Struct::try_from(map.clone()).unwrap();
+        assert_eq!(Map::try_from(value).unwrap(), map);
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    pub fn test_converting_inexact_json() {
+        use serde_json;
+        use std::convert::TryFrom;
+        use std::f64;
+
+        let json = serde_json::Value::from(u64::MAX);
+        assert!(Value::try_from(json).is_err());
+
+        assert!(serde_json::Value::try_from(Value::Number(f64::NAN)).is_err());
+        assert!(serde_json::Value::try_from(Value::Number(f64::INFINITY)).is_err());
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    pub fn test_converting_json_numbers() {
+        use serde_json;
+        use std::convert::TryFrom;
+
+        // Integral doubles become integers, other doubles stay floats
+        let two_pow_60 = (1u64 << 60) as f64;
+
+        for &num in [0.0, 3.0, -7.0, two_pow_60, 1e19].iter() {
+            let json = serde_json::Value::try_from(Value::Number(num)).unwrap();
+            assert!(!json.is_f64(), "expected integer; json={}", json);
+            assert_eq!(json.as_f64(), Some(num));
+            assert_eq!(Value::try_from(json).unwrap(), Value::Number(num));
+        }
+
+        for &num in [-0.0, -2.5, 1e300].iter() {
+            let json = serde_json::Value::try_from(Value::Number(num)).unwrap();
+            assert_eq!(json, serde_json::Value::from(num));
+
+            match Value::try_from(json).unwrap() {
+                Value::Number(val) => assert_eq!(val.to_bits(), num.to_bits()),
+                val => panic!("unexpected value; val={:?}", val),
+            }
+        }
+
+        // Integers are accepted whenever a double holds them exactly
+        let value = Value::try_from(serde_json::Value::from(1u64 << 60)).unwrap();
+        assert_eq!(value, Value::Number(two_pow_60));
+
+        let value = Value::try_from(serde_json::Value::from(-(1i64 << 62))).unwrap();
+        assert_eq!(value, Value::Number(-two_pow_60 * 4.0));
+
+        assert!(Value::try_from(serde_json::Value::from((1i64 << 53) + 1)).is_err());
+        assert!(Value::try_from(serde_json::Value::from(i64::MAX)).is_err());
+    }
+
+    #[cfg(feature = "serde_json")]
+    fn json_sample() -> ::serde_json::Value {
+        let mut nested = ::serde_json::Map::new();
+        nested.insert("ok".to_string(), false.into());
+
+        let mut map = ::serde_json::Map::new();
+        map.insert("name".to_string(), "buffoon".into());
+        map.insert("count".to_string(), 3.into());
+        map.insert("none".to_string(), ::serde_json::Value::Null);
+        map.insert("nested".to_string(), ::serde_json::Value::Object(nested));
+        map.insert("list".to_string(), ::serde_json::Value::Array(vec![1.5.into(), "".into()]));
+
+        ::serde_json::Value::Object(map)
+    }
+}
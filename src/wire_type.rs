@@ -1,4 +1,29 @@
-#[derive(Debug)]
+use ErrorKind;
+use std::io;
+
+/// The largest field number that fits in a field header, 2^29 - 1.
+const MAX_FIELD_NUMBER: u32 = (1 << 29) - 1;
+
+/// Field numbers reserved for use by Protocol Buffers implementations.
+const RESERVED_FIELD_NUMBERS: (u32, u32) = (19_000, 19_999);
+
+/// Checks that `field` may be written to a message. Field 0, field numbers
+/// that do not fit in a field header and the reserved 19000-19999 range are
+/// all rejected.
+pub fn validate_field(field: u32) -> io::Result<()> {
+    let (reserved_min, reserved_max) = RESERVED_FIELD_NUMBERS;
+
+    if field == 0 || field > MAX_FIELD_NUMBER ||
+            (field >= reserved_min && field <= reserved_max) {
+        return Err(ErrorKind::InvalidFieldNumber(field).into());
+    }
+
+    Ok(())
+}
+
+/// The encoding of a field's payload, as stored in the low 3 bits of the field
+/// header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum WireType {
     Varint = 0,
     SixtyFourBit = 1,
@@ -0,0 +1,153 @@
+extern crate buffoon;
+
+use buffoon::*;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+#[derive(Debug, PartialEq)]
+struct Outer {
+    name: String,
+    id: u32,
+    inner: Inner,
+    tags: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Inner {
+    a: u32,
+    b: u32,
+}
+
+impl Serialize for Outer {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        // Deliberately written out of field order
+        try!(out.write(4, &self.inner));
+        try!(out.write_repeated(3, &self.tags));
+        try!(out.write(2, &self.id));
+        try!(out.write(1, &self.name));
+        Ok(())
+    }
+}
+
+impl Deserialize for Outer {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Outer> {
+        let mut name = None;
+        let mut id = None;
+        let mut inner = None;
+        let mut tags = vec![];
+
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => name = Some(try!(f.read())),
+                2 => id = Some(try!(f.read())),
+                3 => tags.push(try!(f.read())),
+                4 => inner = Some(try!(f.read())),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(Outer {
+            name: name.unwrap_or_default(),
+            id: id.unwrap_or(0),
+            inner: inner.unwrap_or(Inner { a: 0, b: 0 }),
+            tags: tags,
+        })
+    }
+}
+
+impl Serialize for Inner {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(2, &self.b));
+        try!(out.write(1, &self.a));
+        Ok(())
+    }
+}
+
+impl Deserialize for Inner {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Inner> {
+        let mut a = 0;
+        let mut b = 0;
+
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => a = try!(f.read()),
+                2 => b = try!(f.read()),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(Inner { a: a, b: b })
+    }
+}
+
+fn outer() -> Outer {
+    Outer {
+        name: "hi".to_string(),
+        id: 5,
+        inner: Inner { a: 1, b: 2 },
+        tags: vec!["x".to_string(), "y".to_string()],
+    }
+}
+
+#[test]
+pub fn test_serializing_fields_in_ascending_order() {
+    let bytes = buffoon::serialize_deterministic(&outer()).unwrap();
+    let expect = b"\x0A\x02hi\x10\x05\x1A\x01x\x1A\x01y\x22\x04\x08\x01\x10\x02";
+
+    assert_eq!(bytes, &expect[..]);
+    assert_eq!(buffoon::deserialize::<Outer, _>(&bytes[..]).unwrap(), outer());
+}
+
+#[test]
+pub fn test_deterministic_serializer_size() {
+    let mut serializer = Serializer::new();
+    serializer.set_deterministic(true);
+    outer().serialize(&mut serializer).unwrap();
+
+    let mut bytes = vec![];
+    serializer.serialize(&outer(), &mut bytes).unwrap();
+
+    assert_eq!(bytes.len(), serializer.size());
+    assert_eq!(bytes, buffoon::serialize_deterministic(&outer()).unwrap());
+}
+
+#[test]
+pub fn test_serializing_map_entries_sorted_by_key() {
+    let mut hash = HashMap::new();
+    let mut tree = BTreeMap::new();
+
+    for i in 0..64u32 {
+        hash.insert(format!("key-{}", i), i);
+        tree.insert(format!("key-{}", i), i);
+    }
+
+    let bytes = buffoon::serialize_deterministic(&hash).unwrap();
+    assert_eq!(bytes, buffoon::serialize(&tree).unwrap());
+
+    let decoded: HashMap<String, u32> = buffoon::deserialize(&bytes[..]).unwrap();
+    assert_eq!(decoded, hash);
+}
+
+#[test]
+pub fn test_checking_canonical_encoding() {
+    let canonical = buffoon::serialize_deterministic(&outer()).unwrap();
+    assert!(buffoon::is_canonical(&canonical).unwrap());
+
+    let unordered = buffoon::serialize(&outer()).unwrap();
+    assert!(canonical != unordered);
+    assert!(!buffoon::is_canonical(&unordered).unwrap());
+
+    assert!(buffoon::is_canonical(b"").unwrap());
+    assert!(buffoon::is_canonical(b"\x08\x01\x08\x02\x10\x03").unwrap());
+    assert!(buffoon::is_canonical(b"\x0A").is_err());
+}
+
+#[test]
+pub fn test_reading_map_entries_with_missing_key_or_value() {
+    let bytes = b"\x0A\x04\x0A\x02hi\x0A\x02\x10\x05";
+    let decoded: HashMap<String, u32> = buffoon::deserialize(&bytes[..]).unwrap();
+
+    assert_eq!(decoded.len(), 2);
+    assert_eq!(decoded["hi"], 0);
+    assert_eq!(decoded[""], 5);
+}
@@ -0,0 +1,166 @@
+extern crate buffoon;
+
+use buffoon::*;
+use buffoon::well_known::TypeName;
+use std::io;
+
+#[derive(Debug, PartialEq, Default)]
+struct Person {
+    name: Option<String>,
+    unknown: UnknownFields,
+}
+
+#[derive(Debug, PartialEq, Default)]
+struct Pet {
+    name: Option<String>,
+}
+
+static NICKNAME: Extension<String> = Extension::new("test.Person", 100, "test.nickname");
+static AGE: Extension<u32> = Extension::new("test.Person", 101, "test.age");
+static PETS: Extension<Pet> = Extension::new("test.Person", 102, "test.pets");
+static LUCKY: Extension<u32> = Extension::new("test.Person", 103, "test.lucky");
+static OTHER: Extension<u32> = Extension::new("test.Other", 100, "test.other");
+
+impl TypeName for Person {
+    fn type_name() -> &'static str {
+        "test.Person"
+    }
+}
+
+impl Extendable for Person {
+    fn unknown_fields(&self) -> &UnknownFields {
+        &self.unknown
+    }
+
+    fn unknown_fields_mut(&mut self) -> &mut UnknownFields {
+        &mut self.unknown
+    }
+}
+
+impl Serialize for Person {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(1, &self.name));
+        try!(self.unknown.serialize(out));
+        Ok(())
+    }
+}
+
+impl Deserialize for Person {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Person> {
+        let mut person = Person::default();
+        try!(person.merge_from(i));
+        Ok(person)
+    }
+}
+
+impl Merge for Person {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => self.name = Some(try!(f.read())),
+                _ => try!(self.unknown.merge_field(f)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Serialize for Pet {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        out.write(1, &self.name)
+    }
+}
+
+impl Deserialize for Pet {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Pet> {
+        let mut pet = Pet::default();
+        try!(pet.merge_from(i));
+        Ok(pet)
+    }
+}
+
+impl Merge for Pet {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => self.name = Some(try!(f.read())),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn pet(name: &str) -> Pet {
+    Pet { name: Some(name.to_string()) }
+}
+
+#[test]
+pub fn test_reading_extensions_from_decoded_message() {
+    // name: "Al", [test.age]: 30, [test.nickname]: "Bud"
+    let bytes = b"\x0A\x02Al\xA8\x06\x1E\xA2\x06\x03Bud";
+    let person: Person = deserialize(&bytes[..]).unwrap();
+
+    assert_eq!(person.name, Some("Al".to_string()));
+    assert!(person.has_extension(&AGE));
+    assert!(!person.has_extension(&PETS));
+
+    assert_eq!(person.get_extension(&AGE).unwrap(), Some(30));
+    assert_eq!(person.get_extension(&NICKNAME).unwrap(), Some("Bud".to_string()));
+    assert_eq!(person.get_extension(&LUCKY).unwrap(), None);
+
+    // Extensions are retained when re-encoding
+    assert_eq!(serialize(&person).unwrap(), &bytes[..]);
+}
+
+#[test]
+pub fn test_setting_extensions() {
+    let mut person = Person::default();
+
+    person.set_extension(&AGE, &30).unwrap();
+    person.set_extension(&AGE, &31).unwrap();
+    person.set_repeated_extension(&PETS, &[pet("Rex"), pet("Tom")]).unwrap();
+
+    let bytes = serialize(&person).unwrap();
+    let decoded: Person = deserialize(&bytes[..]).unwrap();
+
+    assert_eq!(decoded.get_extension(&AGE).unwrap(), Some(31));
+    assert_eq!(decoded.get_repeated_extension(&PETS).unwrap(), vec![pet("Rex"), pet("Tom")]);
+
+    person.clear_extension(&AGE);
+    assert!(!person.has_extension(&AGE));
+    assert!(person.has_extension(&PETS));
+}
+
+#[test]
+pub fn test_accessing_extension_of_other_message() {
+    let mut person = Person::default();
+
+    assert!(person.set_extension(&OTHER, &1).is_err());
+    assert!(person.get_extension(&OTHER).is_err());
+    assert!(!person.has_extension(&OTHER));
+}
+
+#[test]
+pub fn test_decoding_registered_extensions() {
+    let mut registry = ExtensionRegistry::new();
+    registry.register(&AGE);
+    registry.register(&NICKNAME);
+
+    assert_eq!(registry.name("test.Person", 101), Some("test.age"));
+    assert!(!registry.contains("test.Person", 102));
+    assert!(!registry.contains("test.Other", 101));
+
+    let mut person = Person::default();
+    person.set_extension(&NICKNAME, &"Bud".to_string()).unwrap();
+
+    let nickname = registry.decode(&person, 100).unwrap().unwrap();
+    assert_eq!(nickname.downcast_ref::<String>().unwrap(), "Bud");
+
+    assert!(registry.decode(&person, 101).unwrap().is_none());
+
+    let err = registry.decode(&person, 102).unwrap_err();
+    assert_eq!(Error::from_io(&err).unwrap().kind(), ErrorKind::UnknownType);
+}
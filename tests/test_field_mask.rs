@@ -0,0 +1,210 @@
+extern crate buffoon;
+
+use buffoon::*;
+use buffoon::well_known::FieldMask;
+use std::io;
+
+#[derive(Debug, PartialEq, Default)]
+struct Person {
+    name: Option<String>,
+    id: Option<u32>,
+    emails: Vec<String>,
+    address: Option<Address>,
+}
+
+#[derive(Debug, PartialEq, Default)]
+struct Address {
+    street: Option<String>,
+    zip: Option<u32>,
+}
+
+static PERSON: MessageDescriptor = MessageDescriptor {
+    name: "test.Person",
+    fields: &[
+        FieldDescriptor { name: "name", number: 1, repeated: false, message: None },
+        FieldDescriptor { name: "id", number: 2, repeated: false, message: None },
+        FieldDescriptor { name: "emails", number: 3, repeated: true, message: None },
+        FieldDescriptor { name: "address", number: 4, repeated: false, message: Some(Address::descriptor) },
+    ],
+};
+
+static ADDRESS: MessageDescriptor = MessageDescriptor {
+    name: "test.Address",
+    fields: &[
+        FieldDescriptor { name: "street", number: 1, repeated: false, message: None },
+        FieldDescriptor { name: "zip", number: 2, repeated: false, message: None },
+    ],
+};
+
+impl Descriptor for Person {
+    fn descriptor() -> &'static MessageDescriptor {
+        &PERSON
+    }
+}
+
+impl Descriptor for Address {
+    fn descriptor() -> &'static MessageDescriptor {
+        &ADDRESS
+    }
+}
+
+impl Serialize for Person {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(1, &self.name));
+        try!(out.write(2, &self.id));
+        try!(out.write_repeated(3, &self.emails));
+        try!(out.write(4, &self.address));
+        Ok(())
+    }
+}
+
+impl Deserialize for Person {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Person> {
+        let mut person = Person::default();
+        try!(person.merge_from(i));
+        Ok(person)
+    }
+}
+
+impl Merge for Person {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => self.name = Some(try!(f.read())),
+                2 => self.id = Some(try!(f.read())),
+                3 => self.emails.push(try!(f.read())),
+                4 => try!(f.merge_into(&mut self.address)),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(1, &self.street));
+        try!(out.write(2, &self.zip));
+        Ok(())
+    }
+}
+
+impl Deserialize for Address {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Address> {
+        let mut address = Address::default();
+        try!(address.merge_from(i));
+        Ok(address)
+    }
+}
+
+impl Merge for Address {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => self.street = Some(try!(f.read())),
+                2 => self.zip = Some(try!(f.read())),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn person() -> Person {
+    Person {
+        name: Some("Alice".to_string()),
+        id: Some(7),
+        emails: vec!["a@example.com".to_string(), "b@example.com".to_string()],
+        address: Some(Address {
+            street: Some("Main".to_string()),
+            zip: Some(12345),
+        }),
+    }
+}
+
+#[test]
+pub fn test_validating_paths() {
+    assert!(FieldMask::new(vec!["name", "emails", "address.zip"]).validate::<Person>().is_ok());
+
+    for path in ["nickname", "address.country", "emails.domain", "name.first"].iter() {
+        let err = FieldMask::new(vec![*path]).validate::<Person>().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidValue("invalid field mask path"));
+    }
+}
+
+#[test]
+pub fn test_serializing_masked_fields() {
+    let mask = FieldMask::parse("emails,address.zip").unwrap();
+    let bytes = mask.serialize_masked(&person()).unwrap();
+
+    let decoded: Person = deserialize(&bytes[..]).unwrap();
+
+    assert_eq!(decoded, Person {
+        emails: person().emails,
+        address: Some(Address { street: None, zip: Some(12345) }),
+        .. Person::default()
+    });
+}
+
+#[test]
+pub fn test_serializing_overlapping_paths() {
+    let mask = FieldMask::new(vec!["address.zip", "address"]);
+    let bytes = mask.serialize_masked(&person()).unwrap();
+
+    let decoded: Person = deserialize(&bytes[..]).unwrap();
+    assert_eq!(decoded.address, person().address);
+    assert_eq!(decoded.name, None);
+}
+
+#[test]
+pub fn test_merging_masked_fields() {
+    let mut dst = Person {
+        name: Some("Bob".to_string()),
+        id: Some(1),
+        emails: vec![],
+        address: Some(Address { street: Some("Side".to_string()), zip: Some(1) }),
+    };
+
+    let mask = FieldMask::new(vec!["name", "address.zip"]);
+    mask.merge_masked(&person(), &mut dst).unwrap();
+
+    assert_eq!(dst, Person {
+        name: Some("Alice".to_string()),
+        id: Some(1),
+        emails: vec![],
+        address: Some(Address { street: Some("Side".to_string()), zip: Some(12345) }),
+    });
+}
+
+#[test]
+pub fn test_merging_masked_fields_replaces_them() {
+    let mut dst = person();
+
+    let src = Person {
+        id: None,
+        emails: vec!["c@example.com".to_string()],
+        address: Some(Address { street: None, zip: Some(1) }),
+        .. person()
+    };
+
+    let mask = FieldMask::new(vec!["id", "emails", "address.street"]);
+    mask.merge_masked(&src, &mut dst).unwrap();
+
+    assert_eq!(dst, Person {
+        id: None,
+        emails: vec!["c@example.com".to_string()],
+        address: Some(Address { street: None, zip: Some(12345) }),
+        .. person()
+    });
+}
+
+#[test]
+pub fn test_merging_with_invalid_mask() {
+    let mut dst = Person::default();
+    let mask = FieldMask::new(vec!["nickname"]);
+
+    assert!(mask.merge_masked(&person(), &mut dst).is_err());
+    assert_eq!(dst, Person::default());
+}
@@ -0,0 +1,141 @@
+extern crate buffoon;
+
+use buffoon::*;
+use std::io;
+
+#[derive(Debug, PartialEq, Default)]
+struct Envelope {
+    id: u32,
+    payload: Lazy<Payload>,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+struct Payload {
+    a: u32,
+    b: u32,
+}
+
+impl Serialize for Envelope {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(1, &self.id));
+        try!(out.write(2, &self.payload));
+        Ok(())
+    }
+}
+
+impl Deserialize for Envelope {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Envelope> {
+        let mut envelope = Envelope::default();
+        try!(envelope.merge_from(i));
+        Ok(envelope)
+    }
+}
+
+impl Merge for Envelope {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => self.id = try!(f.read()),
+                2 => try!(f.merge_into(&mut self.payload)),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Serialize for Payload {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(1, &self.a));
+        try!(out.write(2, &self.b));
+        Ok(())
+    }
+}
+
+impl Deserialize for Payload {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Payload> {
+        let mut payload = Payload::default();
+        try!(payload.merge_from(i));
+        Ok(payload)
+    }
+}
+
+impl Merge for Payload {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => self.a = try!(f.read()),
+                2 => self.b = try!(f.read()),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// id: 1, payload: { b: 2, a: 1, 9: 3 }
+const ENCODED: &[u8] = b"\x08\x01\x12\x06\x10\x02\x08\x01\x48\x03";
+
+#[test]
+pub fn test_forwarding_untouched_message() {
+    let mut envelope: Envelope = deserialize(ENCODED).unwrap();
+
+    assert!(!envelope.payload.is_decoded());
+    assert_eq!(envelope.payload.raw_bytes(), Some(&b"\x10\x02\x08\x01\x48\x03"[..]));
+
+    // Reading the value does not change the encoding
+    assert_eq!(envelope.payload.get().unwrap(), &Payload { a: 1, b: 2 });
+    assert!(envelope.payload.is_decoded());
+
+    assert_eq!(serialize(&envelope).unwrap(), ENCODED);
+}
+
+#[test]
+pub fn test_reencoding_modified_message() {
+    let mut envelope: Envelope = deserialize(ENCODED).unwrap();
+    envelope.payload.get_mut().unwrap().a = 5;
+
+    assert_eq!(envelope.payload.raw_bytes(), None);
+    assert_eq!(serialize(&envelope).unwrap(), b"\x08\x01\x12\x04\x08\x05\x10\x02");
+}
+
+#[test]
+pub fn test_reencoding_deterministically() {
+    let envelope: Envelope = deserialize(ENCODED).unwrap();
+    let bytes = serialize_deterministic(&envelope).unwrap();
+
+    assert_eq!(bytes, b"\x08\x01\x12\x04\x08\x01\x10\x02");
+}
+
+#[test]
+pub fn test_merging_undecoded_message() {
+    let mut bytes = ENCODED.to_vec();
+    bytes.extend_from_slice(b"\x12\x02\x08\x07");
+
+    let mut envelope: Envelope = deserialize(&bytes[..]).unwrap();
+
+    assert!(!envelope.payload.is_decoded());
+    assert_eq!(envelope.payload.get().unwrap(), &Payload { a: 7, b: 2 });
+    assert_eq!(envelope.payload.clone().into_inner().unwrap(), Payload { a: 7, b: 2 });
+}
+
+#[test]
+pub fn test_reporting_decode_error_on_access() {
+    let mut envelope: Envelope = deserialize(&b"\x12\x02\x08\xff"[..]).unwrap();
+    assert!(envelope.payload.get().is_err());
+    assert!(!envelope.payload.is_decoded());
+    assert!(serialize(&envelope).is_ok());
+}
+
+#[test]
+pub fn test_merging_modified_message() {
+    let mut envelope: Envelope = deserialize(ENCODED).unwrap();
+    envelope.payload.get_mut().unwrap().b = 4;
+
+    merge(&mut envelope, &b"\x12\x02\x08\x07"[..]).unwrap();
+
+    assert_eq!(envelope.payload.raw_bytes(), None);
+    assert_eq!(envelope.payload.get().unwrap(), &Payload { a: 7, b: 4 });
+}
@@ -0,0 +1,158 @@
+extern crate buffoon;
+
+use buffoon::*;
+use std::io;
+
+#[derive(Debug, PartialEq, Default)]
+struct Person {
+    name: Option<String>,
+    id: u32,
+    emails: Vec<String>,
+    address: Option<Address>,
+}
+
+#[derive(Debug, PartialEq, Default)]
+struct Address {
+    street: Option<String>,
+    zip: Option<u32>,
+}
+
+impl Serialize for Person {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(1, &self.name));
+        try!(out.write(2, &self.id));
+        try!(out.write_repeated(3, &self.emails));
+        try!(out.write(4, &self.address));
+        Ok(())
+    }
+}
+
+impl Deserialize for Person {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Person> {
+        let mut person = Person::default();
+        try!(person.merge_from(i));
+        Ok(person)
+    }
+}
+
+impl Merge for Person {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => self.name = Some(try!(f.read())),
+                2 => self.id = try!(f.read()),
+                3 => self.emails.push(try!(f.read())),
+                4 => try!(f.merge_into(&mut self.address)),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(1, &self.street));
+        try!(out.write(2, &self.zip));
+        Ok(())
+    }
+}
+
+impl Deserialize for Address {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Address> {
+        let mut address = Address::default();
+        try!(address.merge_from(i));
+        Ok(address)
+    }
+}
+
+impl Merge for Address {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => self.street = Some(try!(f.read())),
+                2 => self.zip = Some(try!(f.read())),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+pub fn test_merging_into_existing_message() {
+    let mut person = Person {
+        name: Some("Alice".to_string()),
+        id: 1,
+        emails: vec!["alice@example.com".to_string()],
+        address: Some(Address {
+            street: Some("Main St".to_string()),
+            zip: Some(12345),
+        }),
+    };
+
+    let update = Person {
+        name: None,
+        id: 2,
+        emails: vec!["alice@work.example.com".to_string()],
+        address: Some(Address {
+            street: None,
+            zip: Some(54321),
+        }),
+    };
+
+    let bytes = buffoon::serialize(&update).unwrap();
+    buffoon::merge(&mut person, io::Cursor::new(&bytes)).unwrap();
+
+    assert_eq!(person, Person {
+        name: Some("Alice".to_string()),
+        id: 2,
+        emails: vec!["alice@example.com".to_string(), "alice@work.example.com".to_string()],
+        address: Some(Address {
+            street: Some("Main St".to_string()),
+            zip: Some(54321),
+        }),
+    });
+}
+
+#[test]
+pub fn test_deserializing_concatenated_messages() {
+    let a = Person {
+        name: Some("Bob".to_string()),
+        id: 7,
+        emails: vec![],
+        address: Some(Address { street: Some("Elm St".to_string()), zip: None }),
+    };
+
+    let b = Person {
+        name: None,
+        id: 8,
+        emails: vec!["bob@example.com".to_string()],
+        address: Some(Address { street: None, zip: Some(10001) }),
+    };
+
+    let mut bytes = buffoon::serialize(&a).unwrap();
+    bytes.extend(buffoon::serialize(&b).unwrap());
+
+    let person: Person = buffoon::deserialize(io::Cursor::new(&bytes)).unwrap();
+
+    assert_eq!(person, Person {
+        name: Some("Bob".to_string()),
+        id: 8,
+        emails: vec!["bob@example.com".to_string()],
+        address: Some(Address { street: Some("Elm St".to_string()), zip: Some(10001) }),
+    });
+}
+
+#[test]
+pub fn test_merging_tuple() {
+    let mut pair = (1u32, Some(Address { street: Some("Oak St".to_string()), zip: None }));
+    let update = (2u32, Some(Address { street: None, zip: Some(94110) }));
+
+    let bytes = buffoon::serialize(&update).unwrap();
+    buffoon::merge(&mut pair, io::Cursor::new(&bytes)).unwrap();
+
+    assert_eq!(pair, (2, Some(Address { street: Some("Oak St".to_string()), zip: Some(94110) })));
+}
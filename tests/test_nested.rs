@@ -85,3 +85,53 @@ pub fn test_serializing_nested_empty() {
 
     assert_eq!(root, root2);
 }
+
+#[test]
+pub fn test_serializing_nested_empty_packed() {
+    struct Inner;
+    struct Outer;
+
+    impl Serialize for Inner {
+        fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+            out.write_packed(1, Vec::<u32>::new())
+        }
+    }
+
+    impl Serialize for Outer {
+        fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+            try!(out.write(1, &Inner));
+            try!(out.write(2, &Foo { val: 300 }));
+            Ok(())
+        }
+    }
+
+    let expect = b"\x0A\x00\x12\x03\x08\xAC\x02";
+
+    assert_eq!(buffoon::serialize(&Outer).unwrap(), expect);
+    assert_eq!(buffoon::serialize_deterministic(&Outer).unwrap(), expect);
+}
+
+#[test]
+pub fn test_error_path_in_nested_message() {
+    // Root.foo (2) -> Foo.val (1) encoded as a string instead of a varint
+    let bytes = b"\x12\x03\x0A\x01x";
+    let err = buffoon::deserialize::<Root, _>(io::Cursor::new(&bytes[..])).unwrap_err();
+
+    let err = buffoon::Error::from_io(&err).unwrap();
+    assert_eq!(err.path(), [2, 1]);
+    assert_eq!(err.offset(), Some(2));
+    assert_eq!(err.kind(), ErrorKind::WrongWireType {
+        expected: WireType::Varint,
+        actual: WireType::LengthDelimited,
+    });
+}
+
+#[test]
+pub fn test_error_missing_required_field() {
+    let bytes = b"\x12\x00";
+    let err = buffoon::deserialize::<Root, _>(io::Cursor::new(&bytes[..])).unwrap_err();
+
+    let err = buffoon::Error::from_io(&err).unwrap();
+    assert_eq!(err.path(), [2]);
+    assert_eq!(err.kind(), ErrorKind::MissingField("val"));
+}
@@ -0,0 +1,134 @@
+extern crate buffoon;
+
+use buffoon::*;
+use std::io;
+
+#[derive(Debug, PartialEq, Default)]
+struct Samples {
+    doubles: Vec<f64>,
+    floats: Vec<f32>,
+    flags: Vec<bool>,
+    fixed: Vec<Fixed32>,
+    sfixed: Vec<SFixed64>,
+    signed: Vec<SInt64>,
+    counts: Vec<u32>,
+}
+
+impl Serialize for Samples {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write_packed(1, self.doubles.iter().cloned()));
+        try!(out.write_packed(2, self.floats.iter().cloned()));
+        try!(out.write_packed(3, self.flags.iter().cloned()));
+        try!(out.write_packed(4, self.fixed.iter().cloned()));
+        try!(out.write_packed(5, self.sfixed.iter().cloned()));
+        try!(out.write_packed(6, self.signed.iter().cloned()));
+        try!(out.write_packed(7, self.counts.iter().cloned()));
+        Ok(())
+    }
+}
+
+impl Deserialize for Samples {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Samples> {
+        let mut ret = Samples::default();
+
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => try!(f.read_repeated(&mut ret.doubles)),
+                2 => try!(f.read_repeated(&mut ret.floats)),
+                3 => try!(f.read_repeated(&mut ret.flags)),
+                4 => try!(f.read_repeated(&mut ret.fixed)),
+                5 => try!(f.read_repeated(&mut ret.sfixed)),
+                6 => try!(f.read_repeated(&mut ret.signed)),
+                7 => try!(f.read_repeated(&mut ret.counts)),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
+#[test]
+pub fn test_round_tripping_packed_scalars() {
+    let samples = Samples {
+        doubles: vec![1.5, -0.0, 1e300],
+        floats: vec![0.25],
+        flags: vec![true, false, true],
+        fixed: vec![Fixed32(1), Fixed32(!0)],
+        sfixed: vec![SFixed64(-2)],
+        signed: vec![SInt64(-1), SInt64(1), SInt64(-300)],
+        counts: vec![1, 300],
+    };
+
+    let bytes = serialize(&samples).unwrap();
+    assert_eq!(serializer_for(&samples).unwrap().size(), bytes.len());
+    assert_eq!(deserialize::<Samples, _>(&bytes[..]).unwrap(), samples);
+
+    // Deterministic output goes through a different writer
+    assert_eq!(serialize_deterministic(&samples).unwrap(), bytes);
+}
+
+#[test]
+pub fn test_encoding_packed_scalars() {
+    let samples = Samples {
+        floats: vec![1.0, 2.0],
+        flags: vec![true, false],
+        sfixed: vec![SFixed64(-1)],
+        signed: vec![SInt64(-1), SInt64(64)],
+        ..Samples::default()
+    };
+
+    let bytes = serialize(&samples).unwrap();
+
+    assert_eq!(bytes, &b"\x12\x08\x00\x00\x80\x3f\x00\x00\x00\x40\
+                         \x1A\x02\x01\x00\
+                         \x2A\x08\xff\xff\xff\xff\xff\xff\xff\xff\
+                         \x32\x03\x01\x80\x01"[..]);
+}
+
+#[test]
+pub fn test_computing_fixed_width_size_without_iterating() {
+    struct Many;
+
+    impl Serialize for Many {
+        fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+            out.write_packed(1, (0..1000).map(|v| v as f64))
+        }
+    }
+
+    let serializer = serializer_for(&Many).unwrap();
+    assert_eq!(serializer.size(), 1 + 2 + 8000);
+    assert_eq!(serialize(&Many).unwrap().len(), 8003);
+}
+
+#[test]
+pub fn test_reading_truncated_packed_fixed_values() {
+    let bytes = b"\x0A\x05\x00\x00\x00\x00\x00\x00\x00\x00";
+    assert!(deserialize::<Samples, _>(&bytes[..]).is_err());
+}
+
+#[test]
+pub fn test_reading_mixed_packed_and_unpacked_values() {
+    // Field 7 packed, then unpacked, then packed again; field 3 unpacked;
+    // field 1 unpacked
+    let bytes = b"\x3A\x03\x01\xac\x02\x38\x05\x3A\x01\x06\x18\x01\x18\x00\
+                  \x09\x00\x00\x00\x00\x00\x00\xf0\x3f";
+
+    let samples: Samples = deserialize(&bytes[..]).unwrap();
+
+    assert_eq!(samples.counts, [1, 300, 5, 6]);
+    assert_eq!(samples.flags, [true, false]);
+    assert_eq!(samples.doubles, [1.0]);
+}
+
+#[test]
+pub fn test_reading_repeated_values_with_wrong_wire_type() {
+    // Field 1 holds doubles, but is encoded as a varint
+    let err = deserialize::<Samples, _>(&b"\x08\x01"[..]).unwrap_err();
+    let err = Error::from_io(&err).unwrap();
+
+    assert_eq!(err.kind(), ErrorKind::WrongWireType {
+        expected: WireType::SixtyFourBit,
+        actual: WireType::Varint,
+    });
+}
@@ -0,0 +1,114 @@
+extern crate buffoon;
+
+use buffoon::*;
+use std::io;
+
+struct Order {
+    id: u64,
+    customer: Customer,
+    items: Vec<Item>,
+    notes: String,
+}
+
+struct Customer {
+    name: String,
+    email: String,
+}
+
+struct Item {
+    sku: String,
+    quantity: u32,
+    price: f64,
+}
+
+impl Serialize for Order {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(1, &self.id));
+        try!(out.write(2, &self.customer));
+        try!(out.write_repeated(4, &self.items));
+        try!(out.write(5, &self.notes));
+        Ok(())
+    }
+}
+
+impl Serialize for Customer {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(1, &self.name));
+        try!(out.write(2, &self.email));
+        Ok(())
+    }
+}
+
+impl Serialize for Item {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(1, &self.sku));
+        try!(out.write(2, &self.quantity));
+        try!(out.write(3, &self.price));
+        Ok(())
+    }
+}
+
+fn order() -> Vec<u8> {
+    let order = Order {
+        id: 42,
+        customer: Customer {
+            name: "Alice".to_string(),
+            email: "alice@example.com".to_string(),
+        },
+        items: vec![
+            Item { sku: "a-1".to_string(), quantity: 2, price: 9.5 },
+            Item { sku: "b-2".to_string(), quantity: 1, price: 3.25 },
+        ],
+        notes: "leave at door".to_string(),
+    };
+
+    serialize(&order).unwrap()
+}
+
+#[test]
+pub fn test_projecting_nested_paths() {
+    let bytes = order();
+    let projection = Projection::parse(&["2.1", "4.3", "1"]).unwrap();
+    let values = projection.read(&mut InputStream::new(&bytes[..])).unwrap();
+
+    let paths: Vec<_> = values.iter().map(|v| (v.index, v.path.clone())).collect();
+    assert_eq!(paths, vec![
+        (2, vec![1]),
+        (0, vec![2, 1]),
+        (1, vec![4, 3]),
+        (1, vec![4, 3]),
+    ]);
+
+    assert_eq!(values[0].decode::<u64>().unwrap(), 42);
+    assert_eq!(values[1].decode::<String>().unwrap(), "Alice");
+    assert_eq!(values[2].decode::<f64>().unwrap(), 9.5);
+    assert_eq!(values[3].decode::<f64>().unwrap(), 3.25);
+}
+
+#[test]
+pub fn test_projecting_wildcard_paths() {
+    let bytes = order();
+
+    // Field 3 of every nested message. Strings matched by the wildcard, such
+    // as the notes, are not messages and are ignored.
+    let projection = Projection::parse(&["*.3"]).unwrap();
+    let values = projection.read(&mut InputStream::new(&bytes[..])).unwrap();
+
+    let paths: Vec<_> = values.iter().map(|v| v.path.clone()).collect();
+    assert_eq!(paths, vec![vec![4, 3], vec![4, 3]]);
+
+    assert_eq!(values[0].decode::<f64>().unwrap(), 9.5);
+    assert_eq!(values[1].decode::<f64>().unwrap(), 3.25);
+}
+
+#[test]
+pub fn test_projecting_into_non_message() {
+    let bytes = order();
+
+    let projection = Projection::parse(&["5.1"]).unwrap();
+    assert!(projection.read(&mut InputStream::new(&bytes[..])).is_err());
+
+    // Scalars can't be descended into
+    let projection = Projection::parse(&["1.1"]).unwrap();
+    assert!(projection.read(&mut InputStream::new(&bytes[..])).unwrap().is_empty());
+}
@@ -0,0 +1,73 @@
+extern crate buffoon;
+
+use buffoon::*;
+use std::io;
+
+default_value!(DefaultHost: String = "localhost".to_string());
+default_value!(DefaultPort: u32 = 8080);
+
+#[derive(Debug, PartialEq, Default)]
+struct Config {
+    host: Defaulted<String, DefaultHost>,
+    port: Defaulted<u32, DefaultPort>,
+    verbose: Defaulted<bool>,
+}
+
+impl Serialize for Config {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(1, &self.host));
+        try!(out.write(2, &self.port));
+        try!(out.write(3, &self.verbose));
+        Ok(())
+    }
+}
+
+impl Deserialize for Config {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Config> {
+        let mut config = Config::default();
+        try!(config.merge_from(i));
+        Ok(config)
+    }
+}
+
+impl Merge for Config {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => try!(f.merge_into(&mut self.host)),
+                2 => try!(f.merge_into(&mut self.port)),
+                3 => try!(f.merge_into(&mut self.verbose)),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+pub fn test_reading_declared_defaults() {
+    let config: Config = deserialize(&b"\x10\x50"[..]).unwrap();
+
+    assert_eq!(config.host.get(), "localhost");
+    assert!(!config.host.is_set());
+    assert_eq!(*config.port.get(), 80);
+    assert!(config.port.is_set());
+    assert!(!*config.verbose.get());
+}
+
+#[test]
+pub fn test_writing_fields_set_to_default() {
+    let mut config = Config::default();
+    assert!(serialize(&config).unwrap().is_empty());
+
+    config.port.set(8080);
+    config.verbose.set(false);
+
+    let bytes = serialize(&config).unwrap();
+    assert_eq!(bytes, b"\x10\x90\x3F\x18\x00");
+
+    let decoded: Config = deserialize(&bytes[..]).unwrap();
+    assert_eq!(decoded, config);
+    assert!(!decoded.host.is_set());
+}
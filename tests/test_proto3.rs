@@ -0,0 +1,108 @@
+extern crate buffoon;
+
+use buffoon::*;
+use std::collections::BTreeMap;
+use std::io;
+
+#[derive(Debug, PartialEq, Default)]
+struct Scalars {
+    id: u32,
+    name: String,
+    active: bool,
+    score: f64,
+    delta: i64,
+    count: Option<u32>,
+    samples: Vec<u32>,
+    labels: BTreeMap<String, u32>,
+}
+
+impl Serialize for Scalars {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(1, &self.id));
+        try!(out.write(2, &self.name));
+        try!(out.write(3, &self.active));
+        try!(out.write(4, &self.score));
+        try!(out.write(5, &self.delta));
+        try!(out.write(6, &self.count));
+        try!(out.write_repeated(7, &self.samples));
+        try!(out.write(8, &self.labels));
+        Ok(())
+    }
+}
+
+#[test]
+pub fn test_omitting_default_scalars() {
+    let bytes = buffoon::serialize_proto3(&Scalars::default()).unwrap();
+    assert!(bytes.is_empty(), "{:?}", bytes);
+
+    // Without proto3 mode, defaults are written
+    let bytes = buffoon::serialize(&Scalars::default()).unwrap();
+    assert_eq!(bytes, b"\x08\x00\x12\x00\x18\x00\x21\x00\x00\x00\x00\x00\x00\x00\x00\x28\x00");
+}
+
+#[test]
+pub fn test_writing_non_default_scalars() {
+    let msg = Scalars {
+        id: 1,
+        name: "a".to_string(),
+        active: true,
+        score: -0.0,
+        delta: -1,
+        .. Scalars::default()
+    };
+
+    let bytes = buffoon::serialize_proto3(&msg).unwrap();
+    let mut expect = b"\x08\x01\x12\x01a\x18\x01\x21\x00\x00\x00\x00\x00\x00\x00\x80".to_vec();
+    expect.extend_from_slice(b"\x28\xff\xff\xff\xff\xff\xff\xff\xff\xff\x01");
+
+    assert_eq!(bytes, expect);
+}
+
+#[test]
+pub fn test_keeping_explicit_presence() {
+    let msg = Scalars {
+        count: Some(0),
+        samples: vec![0, 2],
+        .. Scalars::default()
+    };
+
+    let bytes = buffoon::serialize_proto3(&msg).unwrap();
+    assert_eq!(bytes, b"\x30\x00\x38\x00\x38\x02");
+}
+
+#[test]
+pub fn test_writing_default_map_entries() {
+    let mut msg = Scalars::default();
+    msg.labels.insert("".to_string(), 0);
+
+    let bytes = buffoon::serialize_proto3(&msg).unwrap();
+    assert_eq!(bytes, b"\x42\x04\x0A\x00\x10\x00");
+}
+
+#[test]
+pub fn test_omitting_default_tuple_elements() {
+    let bytes = buffoon::serialize_proto3(&(0u32, "a".to_string())).unwrap();
+    assert_eq!(bytes, b"\x12\x01a");
+}
+
+#[test]
+pub fn test_proto3_serializer_size() {
+    let msg = Scalars {
+        id: 5,
+        count: Some(0),
+        .. Scalars::default()
+    };
+
+    for &deterministic in [false, true].iter() {
+        let mut serializer = Serializer::new();
+        serializer.set_proto3(true);
+        serializer.set_deterministic(deterministic);
+        msg.serialize(&mut serializer).unwrap();
+
+        let mut bytes = vec![];
+        serializer.serialize(&msg, &mut bytes).unwrap();
+
+        assert_eq!(bytes.len(), serializer.size());
+        assert_eq!(bytes, b"\x08\x05\x30\x00");
+    }
+}
@@ -0,0 +1,156 @@
+extern crate buffoon;
+
+use buffoon::*;
+use std::borrow::Cow;
+use std::io;
+use std::rc::Rc;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Node {
+    value: u32,
+    next: Option<Box<Node>>,
+}
+
+impl Serialize for Node {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(1, &self.value));
+        try!(out.write(2, &self.next));
+        Ok(())
+    }
+}
+
+impl Deserialize for Node {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Node> {
+        let mut ret = Node::default();
+        try!(ret.merge_from(i));
+        Ok(ret)
+    }
+}
+
+impl Merge for Node {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => self.value = try!(f.read()),
+                2 => try!(f.merge_into(&mut self.next)),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Config<'a> {
+    name: Cow<'a, str>,
+    data: Cow<'a, [u8]>,
+    label: Box<str>,
+    blob: Box<[u8]>,
+    shared: Arc<Node>,
+    local: Rc<Node>,
+}
+
+impl<'a> Serialize for Config<'a> {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(1, &self.name));
+        try!(out.write(2, &self.data));
+        try!(out.write(3, &self.label));
+        try!(out.write(4, &self.blob));
+        try!(out.write(5, &self.shared));
+        try!(out.write(6, &self.local));
+        Ok(())
+    }
+}
+
+impl<'a> Deserialize for Config<'a> {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Config<'a>> {
+        let mut ret = Config::default();
+        try!(ret.merge_from(i));
+        Ok(ret)
+    }
+}
+
+impl<'a> Merge for Config<'a> {
+    fn merge_from<R: io::Read>(&mut self, i: &mut InputStream<R>) -> io::Result<()> {
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => self.name = try!(f.read()),
+                2 => self.data = try!(f.read()),
+                3 => self.label = try!(f.read()),
+                4 => self.blob = try!(f.read()),
+                5 => try!(f.merge_into(&mut self.shared)),
+                6 => try!(f.merge_into(&mut self.local)),
+                _ => try!(f.skip()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn list(values: &[u32]) -> Option<Box<Node>> {
+    values.iter().rev().fold(None, |next, &value| {
+        Some(Box::new(Node { value: value, next: next }))
+    })
+}
+
+#[test]
+pub fn test_round_tripping_recursive_message() {
+    let head = *list(&[1, 2, 3]).unwrap();
+    let bytes = serialize(&head).unwrap();
+
+    assert_eq!(bytes, b"\x08\x01\x12\x06\x08\x02\x12\x02\x08\x03");
+    assert_eq!(deserialize::<Node, _>(&bytes[..]).unwrap(), head);
+
+    // Boxed messages are encoded as the message itself
+    assert_eq!(serialize(&Box::new(head)).unwrap(), bytes);
+}
+
+#[test]
+pub fn test_merging_recursive_message() {
+    let mut head = *list(&[1, 2]).unwrap();
+
+    // Sets the value of the second node and appends a third
+    merge(&mut head, &b"\x12\x06\x08\x05\x12\x02\x08\x06"[..]).unwrap();
+
+    assert_eq!(head, *list(&[1, 5, 6]).unwrap());
+}
+
+#[test]
+pub fn test_round_tripping_pointers_and_cows() {
+    let data = [1u8, 2, 3];
+    let shared = Arc::new(Node { value: 7, next: None });
+
+    let config = Config {
+        name: Cow::Borrowed("borrowed"),
+        data: Cow::Borrowed(&data[..]),
+        label: "label".to_string().into_boxed_str(),
+        blob: vec![9, 9].into_boxed_slice(),
+        shared: shared.clone(),
+        local: Rc::new(Node { value: 8, next: None }),
+    };
+
+    let bytes = serialize(&config).unwrap();
+    let decoded: Config = deserialize(&bytes[..]).unwrap();
+
+    assert_eq!(decoded, config);
+    assert!(matches!(decoded.name, Cow::Owned(_)));
+
+    // Proto3 defaults see through pointers
+    assert!(serialize_proto3(&Config::default()).unwrap().len() < serialize(&Config::default()).unwrap().len());
+}
+
+#[test]
+pub fn test_merging_into_shared_value() {
+    let shared = Arc::new(Node { value: 1, next: None });
+
+    let mut config = Config { shared: shared.clone(), ..Config::default() };
+
+    merge(&mut config, &b"\x2A\x02\x08\x02"[..]).unwrap();
+
+    // The shared value is cloned rather than modified in place
+    assert_eq!(config.shared.value, 2);
+    assert_eq!(shared.value, 1);
+}
@@ -0,0 +1,182 @@
+extern crate buffoon;
+
+use buffoon::*;
+use std::io;
+
+#[derive(Debug, PartialEq, Default)]
+struct Grid {
+    origin: [i32; 2],
+    cells: Vec<u32>,
+}
+
+impl Serialize for Grid {
+    fn serialize<O: OutputStream>(&self, out: &mut O) -> io::Result<()> {
+        try!(out.write(1, &self.origin));
+        try!(out.write_repeated(2, &self.cells));
+        Ok(())
+    }
+}
+
+impl Deserialize for Grid {
+    fn deserialize<R: io::Read>(i: &mut InputStream<R>) -> io::Result<Grid> {
+        let mut origin = vec![];
+        let mut cells = vec![];
+
+        while let Some(f) = try!(i.read_field()) {
+            match f.tag() {
+                1 => try!(f.read_repeated(&mut origin)),
+                2 => try!(f.read_repeated(&mut cells)),
+                _ => try!(f.skip()),
+            }
+        }
+
+        if origin.len() != 2 {
+            return Err(Error::new(ErrorKind::InvalidValue("expected 2 coordinates")).into());
+        }
+
+        Ok(Grid { origin: [origin[0], origin[1]], cells: cells })
+    }
+}
+
+type Twelve = (u32, String, u64, bool, i32, f64, Vec<u8>, u32, Option<u32>, i64, f32, (u32, u32));
+
+fn assert_invalid<T: Deserialize>(bytes: &[u8]) {
+    let err = deserialize::<T, _>(bytes).err().expect("decoding should fail");
+    let err = Error::from_io(&err).unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidValue("wrong number of array elements"));
+}
+
+#[test]
+pub fn test_round_tripping_large_tuples() {
+    let tuple = (1u32, "two".to_string(), 3u64, true, 5i32, 6.5f64,
+                 vec![7u8], 8u32, Some(9u32), 10i64, 11.5f32, (12u32, 13u32));
+
+    let bytes = serialize(&tuple).unwrap();
+    assert_eq!(&bytes[..5], b"\x08\x01\x12\x03t");
+
+    let decoded: Twelve = deserialize(&bytes[..]).unwrap();
+
+    assert_eq!(decoded, tuple);
+}
+
+#[test]
+pub fn test_tuple_elements_are_numbered_from_one() {
+    assert_eq!(serialize(&("a".to_string(),)).unwrap(), b"\x0A\x01a");
+    assert_eq!(serialize(&(0u32, 0u32, 5u32)).unwrap(), b"\x08\x00\x10\x00\x18\x05");
+
+    // Elements at their default value are omitted in proto3, like any field
+    assert_eq!(serialize_proto3(&(0u32, 0u32, 5u32)).unwrap(), b"\x18\x05");
+    assert_eq!(serialize_proto3(&(Some(0u32),)).unwrap(), b"\x08\x00");
+}
+
+#[test]
+pub fn test_reading_tuple_missing_element() {
+    let err = deserialize::<(u32, u32, u32), _>(&b"\x08\x01\x18\x03"[..]).unwrap_err();
+    let err = Error::from_io(&err).unwrap();
+    assert_eq!(err.kind(), ErrorKind::MissingField("tuple::1"));
+}
+
+#[test]
+pub fn test_merging_large_tuple() {
+    let mut tuple = (1u32, (1u32, 2u32), Some(3u32));
+
+    // Nested tuples are merged, keeping the elements absent from the input
+    merge(&mut tuple, &b"\x12\x02\x10\x05\x08\x04"[..]).unwrap();
+
+    assert_eq!(tuple, (4, (1, 5), Some(3)));
+}
+
+#[test]
+pub fn test_round_tripping_arrays() {
+    let arr = [1u32, 0, 300];
+    let bytes = serialize(&arr).unwrap();
+
+    // Every element is written, including defaults
+    assert_eq!(bytes, b"\x08\x01\x08\x00\x08\xAC\x02");
+    assert_eq!(serialize_proto3(&arr).unwrap(), bytes);
+    assert_eq!(deserialize::<[u32; 3], _>(&bytes[..]).unwrap(), arr);
+
+    let names = ["a".to_string(), "b".to_string()];
+    let bytes = serialize(&names).unwrap();
+    assert_eq!(deserialize::<[String; 2], _>(&bytes[..]).unwrap(), names);
+
+    assert_eq!(serialize(&[0u32; 0]).unwrap(), b"");
+    assert_eq!(deserialize::<[u32; 0], _>(&b""[..]).unwrap(), [0u32; 0]);
+}
+
+#[test]
+pub fn test_reading_array_with_wrong_length() {
+    let bytes = serialize(&[1u32, 2, 3]).unwrap();
+
+    assert_invalid::<[u32; 2]>(&bytes);
+    assert_invalid::<[u32; 4]>(&bytes);
+    assert_invalid::<[u32; 0]>(&bytes);
+}
+
+#[test]
+pub fn test_writing_array_as_repeated_field() {
+    let grid = Grid { origin: [-1, 4], cells: vec![7] };
+    let bytes = serialize(&grid).unwrap();
+
+    assert_eq!(bytes, b"\x08\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\x01\x08\x04\x10\x07");
+    assert_eq!(deserialize::<Grid, _>(&bytes[..]).unwrap(), grid);
+
+    // Arrays are gathered from the fields of nested tuples too
+    let one: (([u32; 1],),) = deserialize(&b"\x0A\x02\x08\x05"[..]).unwrap();
+    assert_eq!(one, (([5],),));
+}
+
+#[test]
+pub fn test_reading_array_field_from_single_occurrence() {
+    let bytes = serialize(&Grid { origin: [-1, 4], cells: vec![] }).unwrap();
+    let mut input = InputStream::new(&bytes[..]);
+
+    // An unpacked array field spans several occurrences, of which
+    // `Field::read` only sees the first
+    let f = input.read_field().unwrap().unwrap();
+    let err = f.read::<[i32; 2]>().unwrap_err();
+    let err = Error::from_io(&err).unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidValue("wrong number of array elements"));
+
+    // A packed array field is a single occurrence
+    let mut input = InputStream::new(&b"\x0A\x02\x01\x02"[..]);
+    let f = input.read_field().unwrap().unwrap();
+    assert_eq!(f.read::<[u32; 2]>().unwrap(), [1, 2]);
+}
+
+#[test]
+pub fn test_round_tripping_tuples_holding_arrays() {
+    let tuple = (1u32, [1u32, 2, 3]);
+    let bytes = serialize(&tuple).unwrap();
+
+    assert_eq!(bytes, b"\x08\x01\x10\x01\x10\x02\x10\x03");
+    assert_eq!(deserialize::<(u32, [u32; 3]), _>(&bytes[..]).unwrap(), tuple);
+
+    // Occurrences of the array's field need not be contiguous
+    let bytes = b"\x0A\x01a\x10\x05\x0A\x01b\x1A\x04\x08\x07\x10\x09";
+    let tuple: ([String; 2], u32, (u64, Box<[u64; 1]>)) = deserialize(&bytes[..]).unwrap();
+    assert_eq!(tuple, (["a".to_string(), "b".to_string()], 5, (7, Box::new([9]))));
+
+    let bytes = serialize(&tuple).unwrap();
+    assert_eq!(deserialize::<([String; 2], u32, (u64, Box<[u64; 1]>)), _>(&bytes[..]).unwrap(), tuple);
+}
+
+#[test]
+pub fn test_reading_tuple_holding_array_with_wrong_length() {
+    let bytes = serialize(&(1u32, [1u32, 2, 3])).unwrap();
+
+    let err = deserialize::<(u32, [u32; 2]), _>(&bytes[..]).unwrap_err();
+    let err = Error::from_io(&err).unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidValue("wrong number of array elements"));
+    assert_eq!(err.path(), [2]);
+}
+
+#[test]
+pub fn test_reading_packed_arrays() {
+    assert_eq!(deserialize::<[u32; 2], _>(&b"\x0A\x02\x01\x02"[..]).unwrap(), [1, 2]);
+    assert_invalid::<[u32; 1]>(b"\x0A\x02\x01\x02");
+
+    // Packed and unpacked occurrences can be mixed
+    let tuple: (u32, [f32; 3]) = deserialize(&b"\x08\x01\x12\x04\x00\x00\x80\x3F\x15\x00\x00\x00\x40\x15\x00\x00\x40\x40"[..]).unwrap();
+    assert_eq!(tuple, (1, [1.0, 2.0, 3.0]));
+}